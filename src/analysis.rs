@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+use crate::flow::Endpoint;
+use crate::packet::Packet;
+use crate::stream::StreamKey;
+use crate::tcp::{seq_after, seq_before, TcpFlags, TcpOption};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcpAnalysisFlag {
+    Retransmission,
+    FastRetransmission,
+    SpuriousRetransmission,
+    // Counts the duplicates of the same ACK, starting from 1.
+    DuplicateAck(u32),
+    ZeroWindow,
+    WindowFull,
+    KeepAlive,
+    OutOfOrder,
+    PreviousSegmentNotCaptured,
+}
+
+impl fmt::Display for TcpAnalysisFlag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Retransmission => write!(f, "TCP Retransmission"),
+            Self::FastRetransmission => write!(f, "TCP Fast Retransmission"),
+            Self::SpuriousRetransmission => write!(f, "TCP Spurious Retransmission"),
+            Self::DuplicateAck(count) => write!(f, "TCP Dup ACK #{}", count),
+            Self::ZeroWindow => write!(f, "TCP ZeroWindow"),
+            Self::WindowFull => write!(f, "TCP Window Full"),
+            Self::KeepAlive => write!(f, "TCP Keep-Alive"),
+            Self::OutOfOrder => write!(f, "TCP Out-Of-Order"),
+            Self::PreviousSegmentNotCaptured => write!(f, "TCP Previous segment not captured"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnalysisConfig {
+    // A segment filling a hole this soon after the previous one is taken to
+    // be reordered rather than retransmitted.
+    pub out_of_order_threshold: Duration,
+    pub idle_timeout: Duration,
+}
+
+impl Default for AnalysisConfig {
+    fn default() -> Self {
+        AnalysisConfig {
+            out_of_order_threshold: Duration::from_millis(3),
+            idle_timeout: Duration::from_secs(300),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AnalysisSummary {
+    pub retransmissions: u64,
+    pub fast_retransmissions: u64,
+    pub spurious_retransmissions: u64,
+    pub duplicate_acks: u64,
+    pub zero_windows: u64,
+    pub window_full: u64,
+    pub keep_alives: u64,
+    pub out_of_order: u64,
+    pub previous_segment_not_captured: u64,
+}
+
+impl AnalysisSummary {
+    pub fn record(&mut self, flags: &[TcpAnalysisFlag]) {
+        for flag in flags {
+            match flag {
+                TcpAnalysisFlag::Retransmission => self.retransmissions += 1,
+                TcpAnalysisFlag::FastRetransmission => self.fast_retransmissions += 1,
+                TcpAnalysisFlag::SpuriousRetransmission => self.spurious_retransmissions += 1,
+                TcpAnalysisFlag::DuplicateAck(_) => self.duplicate_acks += 1,
+                TcpAnalysisFlag::ZeroWindow => self.zero_windows += 1,
+                TcpAnalysisFlag::WindowFull => self.window_full += 1,
+                TcpAnalysisFlag::KeepAlive => self.keep_alives += 1,
+                TcpAnalysisFlag::OutOfOrder => self.out_of_order += 1,
+                TcpAnalysisFlag::PreviousSegmentNotCaptured => self.previous_segment_not_captured += 1,
+            }
+        }
+    }
+}
+
+impl fmt::Display for AnalysisSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} retransmissions, {} fast retransmissions, {} spurious retransmissions, {} duplicate ACKs, \
+             {} zero windows, {} window full, {} keep-alives, {} out-of-order, {} previous segment not captured",
+            self.retransmissions,
+            self.fast_retransmissions,
+            self.spurious_retransmissions,
+            self.duplicate_acks,
+            self.zero_windows,
+            self.window_full,
+            self.keep_alives,
+            self.out_of_order,
+            self.previous_segment_not_captured
+        )
+    }
+}
+
+// What has been seen of one direction of a connection.
+#[derive(Debug, Clone, Copy, Default)]
+struct SideState {
+    // Sequence number following the highest byte sent so far.
+    next_seq: Option<u32>,
+    last_segment_time: Duration,
+    last_ack: Option<u32>,
+    // Window as advertised in the last segment, before scaling.
+    last_window: u16,
+    // The window in a SYN is never scaled.
+    last_window_in_syn: bool,
+    duplicate_acks: u32,
+    window_scale: Option<u8>,
+    last_seen: Duration,
+}
+
+#[derive(Debug)]
+pub struct TcpAnalyzer {
+    config: AnalysisConfig,
+    sides: HashMap<StreamKey, SideState>,
+    summary: AnalysisSummary,
+}
+
+impl TcpAnalyzer {
+    pub fn new(config: AnalysisConfig) -> Self {
+        TcpAnalyzer { config, sides: HashMap::new(), summary: AnalysisSummary::default() }
+    }
+
+    pub fn summary(&self) -> &AnalysisSummary {
+        &self.summary
+    }
+
+    pub fn expire(&mut self, now: Duration) {
+        let timeout = self.config.idle_timeout;
+        self.sides.retain(|_, side| now.saturating_sub(side.last_seen) <= timeout);
+    }
+
+    pub fn analyze(&mut self, now: Duration, packet: &Packet) -> Vec<TcpAnalysisFlag> {
+        let (segment, (source_addr, dest_addr)) = match (packet.tcp(), packet.ip_addresses()) {
+            (Some(segment), Some(addresses)) => (segment, addresses),
+            _ => return vec![],
+        };
+        let key = StreamKey {
+            source: Endpoint { addr: source_addr, port: segment.source_port },
+            dest: Endpoint { addr: dest_addr, port: segment.dest_port },
+        };
+        let flags = segment.flags;
+        let control = flags.intersects(TcpFlags::SYN | TcpFlags::FIN | TcpFlags::RST);
+        let seq = segment.sequence_no;
+        let length = segment.sequence_length(packet.payload.len());
+        let end = seq.wrapping_add(length);
+        let reverse = self.sides.get(&key.reverse()).copied().unwrap_or_default();
+        let side = self.sides.entry(key).or_default();
+        let mut analysis: Vec<TcpAnalysisFlag> = vec![];
+
+        if flags.contains(TcpFlags::SYN) {
+            side.window_scale = segment.options.iter().flatten().find_map(|option| match option {
+                TcpOption::WindowScale(shift) => Some(*shift),
+                _ => None,
+            });
+        }
+        // Window scaling only applies once both sides have offered it.
+        let reverse_scale = match (reverse.window_scale, side.window_scale) {
+            (Some(shift), Some(_)) if !reverse.last_window_in_syn => shift.min(14),
+            _ => 0,
+        };
+
+        if segment.window == 0 && !control {
+            analysis.push(TcpAnalysisFlag::ZeroWindow);
+        }
+        let keep_alive = length <= 1
+            && !control
+            && side.next_seq.is_some_and(|next_seq| seq == next_seq.wrapping_sub(1));
+        if keep_alive {
+            analysis.push(TcpAnalysisFlag::KeepAlive);
+        }
+        if side.next_seq.is_some_and(|next_seq| seq_after(seq, next_seq)) && !flags.contains(TcpFlags::RST) {
+            analysis.push(TcpAnalysisFlag::PreviousSegmentNotCaptured);
+        }
+        if length > 0 && !keep_alive {
+            if let Some(reverse_ack) = reverse.last_ack {
+                let window_end = reverse_ack.wrapping_add((reverse.last_window as u32) << reverse_scale);
+                if reverse.last_window != 0 && end == window_end {
+                    analysis.push(TcpAnalysisFlag::WindowFull);
+                }
+            }
+        }
+
+        // A keep-alive repeats the ACK too, but sits one byte before the next sequence number.
+        let is_duplicate_ack = length == 0
+            && !control
+            && flags.contains(TcpFlags::ACK)
+            && side.next_seq.is_none_or(|next_seq| seq == next_seq)
+            && side.last_ack == Some(segment.ack_no)
+            && side.last_window == segment.window
+            && segment.window != 0;
+        if is_duplicate_ack {
+            side.duplicate_acks += 1;
+            analysis.push(TcpAnalysisFlag::DuplicateAck(side.duplicate_acks));
+        } else if side.last_ack != Some(segment.ack_no) || length > 0 {
+            side.duplicate_acks = 0;
+        }
+
+        let old_data = length > 0
+            && !keep_alive
+            && side.next_seq.is_some_and(|next_seq| seq_before(seq, next_seq));
+        if old_data {
+            let since_last = now.saturating_sub(side.last_segment_time);
+            if reverse.duplicate_acks >= 2 && reverse.last_ack == Some(seq) {
+                analysis.push(TcpAnalysisFlag::FastRetransmission);
+            } else if since_last < self.config.out_of_order_threshold && side.next_seq != Some(end) {
+                analysis.push(TcpAnalysisFlag::OutOfOrder);
+            } else if reverse.last_ack.is_some_and(|ack| !seq_after(end, ack)) {
+                analysis.push(TcpAnalysisFlag::SpuriousRetransmission);
+            } else {
+                analysis.push(TcpAnalysisFlag::Retransmission);
+            }
+        }
+
+        if side.next_seq.is_none_or(|next_seq| seq_after(end, next_seq)) {
+            side.next_seq = Some(end);
+        }
+        if length > 0 {
+            side.last_segment_time = now;
+        }
+        if flags.contains(TcpFlags::ACK) {
+            side.last_ack = Some(segment.ack_no);
+        }
+        side.last_window = segment.window;
+        side.last_window_in_syn = flags.contains(TcpFlags::SYN);
+        side.last_seen = now;
+
+        self.summary.record(&analysis);
+        analysis
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{dissect, LinkType};
+    use crate::testing::tcp_with;
+    use TcpAnalysisFlag::*;
+
+    const ACK: TcpFlags = TcpFlags::ACK;
+
+    struct Connection {
+        analyzer: TcpAnalyzer,
+    }
+
+    impl Connection {
+        // Completes a handshake with the client's data starting at sequence
+        // number 1 and the server's at 5001. `options` go on both SYNs.
+        fn open(options: &[u8]) -> Self {
+            let mut connection = Connection { analyzer: TcpAnalyzer::new(AnalysisConfig::default()) };
+            let syn = tcp_with(true, 0, 0, TcpFlags::SYN, 65535, options, &[]);
+            let syn_ack = tcp_with(false, 5000, 1, TcpFlags::SYN | ACK, 65535, options, &[]);
+            for bytes in [syn, syn_ack] {
+                assert!(connection.analyzer.analyze(Duration::ZERO, &dissect(LinkType::IPv4, &bytes)).is_empty());
+            }
+            assert!(connection.client(0, 1, b"").is_empty());
+            connection
+        }
+
+        fn send(&mut self, millis: u64, from_client: bool, seq: u32, ack: u32, window: u16, payload: &[u8]) -> Vec<TcpAnalysisFlag> {
+            let bytes = tcp_with(from_client, seq, ack, ACK, window, &[], payload);
+            self.analyzer.analyze(Duration::from_millis(millis), &dissect(LinkType::IPv4, &bytes))
+        }
+
+        fn client(&mut self, millis: u64, seq: u32, payload: &[u8]) -> Vec<TcpAnalysisFlag> {
+            self.send(millis, true, seq, 5001, 65535, payload)
+        }
+
+        fn server_ack(&mut self, millis: u64, ack: u32, window: u16) -> Vec<TcpAnalysisFlag> {
+            self.send(millis, false, 5001, ack, window, &[])
+        }
+    }
+
+    #[test]
+    fn in_order_exchange_is_not_flagged() {
+        let mut connection = Connection::open(&[]);
+        assert!(connection.client(10, 1, b"abcd").is_empty());
+        assert!(connection.client(20, 5, b"efgh").is_empty());
+        assert!(connection.server_ack(30, 9, 65535).is_empty());
+        assert!(connection.send(40, false, 5001, 9, 65535, b"reply").is_empty());
+    }
+
+    #[test]
+    fn retransmission() {
+        let mut connection = Connection::open(&[]);
+        connection.client(10, 1, b"abcd");
+        assert_eq!(connection.client(500, 1, b"abcd"), [Retransmission]);
+        assert_eq!(connection.analyzer.summary().retransmissions, 1);
+    }
+
+    #[test]
+    fn spurious_retransmission_of_acknowledged_data() {
+        let mut connection = Connection::open(&[]);
+        connection.client(10, 1, b"abcd");
+        connection.server_ack(20, 5, 65535);
+        assert_eq!(connection.client(500, 1, b"abcd"), [SpuriousRetransmission]);
+    }
+
+    #[test]
+    fn out_of_order_and_previous_segment_not_captured() {
+        let mut connection = Connection::open(&[]);
+        assert_eq!(connection.client(10, 5, b"efgh"), [PreviousSegmentNotCaptured]);
+        assert_eq!(connection.client(11, 1, b"abcd"), [OutOfOrder]);
+    }
+
+    #[test]
+    fn duplicate_acks_and_fast_retransmission() {
+        let mut connection = Connection::open(&[]);
+        connection.client(10, 1, b"abcd");
+        connection.client(11, 5, b"efgh");
+        connection.client(12, 9, b"ijkl");
+        assert!(connection.server_ack(20, 5, 65535).is_empty());
+        assert_eq!(connection.server_ack(21, 5, 65535), [DuplicateAck(1)]);
+        assert_eq!(connection.server_ack(22, 5, 65535), [DuplicateAck(2)]);
+        assert_eq!(connection.client(30, 5, b"efgh"), [FastRetransmission]);
+        // A new ACK resets the count.
+        assert!(connection.server_ack(40, 13, 65535).is_empty());
+        assert!(connection.server_ack(41, 13, 32768).is_empty());
+        assert_eq!(connection.analyzer.summary().duplicate_acks, 2);
+    }
+
+    #[test]
+    fn zero_window() {
+        let mut connection = Connection::open(&[]);
+        connection.client(10, 1, b"abcd");
+        assert_eq!(connection.server_ack(20, 5, 0), [ZeroWindow]);
+        // Repeating a zero window is not a duplicate ACK.
+        assert_eq!(connection.server_ack(30, 5, 0), [ZeroWindow]);
+    }
+
+    #[test]
+    fn window_full() {
+        let mut connection = Connection::open(&[]);
+        connection.server_ack(10, 1, 8);
+        assert!(connection.client(20, 1, b"abcd").is_empty());
+        assert_eq!(connection.client(21, 5, b"efgh"), [WindowFull]);
+    }
+
+    #[test]
+    fn window_full_with_scaled_window() {
+        // NOP and a window scale of 2.
+        let mut connection = Connection::open(&[1, 3, 3, 2]);
+        connection.server_ack(10, 1, 2);
+        assert_eq!(connection.client(20, 1, b"abcdefgh"), [WindowFull]);
+    }
+
+    #[test]
+    fn keep_alive() {
+        let mut connection = Connection::open(&[]);
+        connection.client(10, 1, b"abcd");
+        connection.server_ack(20, 5, 65535);
+        assert_eq!(connection.client(1000, 4, b""), [KeepAlive]);
+        assert_eq!(connection.client(2000, 4, b"d"), [KeepAlive]);
+    }
+
+    #[test]
+    fn flags_are_tracked_per_connection() {
+        let mut connection = Connection::open(&[]);
+        connection.client(10, 1, b"abcd");
+        // The server's first data after the handshake is not a retransmission
+        // of the client's.
+        assert!(connection.send(20, false, 5001, 5, 65535, b"abcd").is_empty());
+        let summary = connection.analyzer.summary();
+        assert_eq!(summary.retransmissions + summary.out_of_order, 0);
+    }
+}
@@ -1,16 +1,27 @@
-use std::array::TryFromSliceError;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use crate::bytes::{ensure_length, read_u16, read_u8};
+use crate::error::ParseError;
 use crate::ethernet::MacAddress;
 
 #[derive(Debug)]
 pub enum HardwareType {
     Ethernet,
+    Ieee802,
+    Arcnet,
+    FrameRelay,
+    Atm,
+    Hdlc,
+    FibreChannel,
+    Serial,
+    Ieee1394,
+    InfiniBand,
     Other(u16),
 }
 
 #[derive(Debug)]
 pub enum ProtocolType {
     IPv4,
+    IPv6,
     Other(u16),
 }
 
@@ -18,6 +29,15 @@ impl From<u16> for HardwareType {
     fn from(raw: u16) -> Self {
         match raw {
             0x0001 => Self::Ethernet,
+            0x0006 => Self::Ieee802,
+            0x0007 => Self::Arcnet,
+            0x000F => Self::FrameRelay,
+            0x0010 | 0x0013 => Self::Atm,
+            0x0011 => Self::Hdlc,
+            0x0012 => Self::FibreChannel,
+            0x0014 => Self::Serial,
+            0x0018 => Self::Ieee1394,
+            0x0020 => Self::InfiniBand,
             other => Self::Other(other),
         }
     }
@@ -27,6 +47,7 @@ impl From<u16> for ProtocolType {
     fn from(raw: u16) -> Self {
         match raw {
             0x0800 => Self::IPv4,
+            0x86DD => Self::IPv6,
             other => Self::Other(other),
         }
     }
@@ -36,6 +57,12 @@ impl From<u16> for ProtocolType {
 pub enum Operation {
     Request,
     Reply,
+    // RARP (RFC 903)
+    ReverseRequest,
+    ReverseReply,
+    // Inverse ARP (RFC 2390)
+    InverseRequest,
+    InverseReply,
     Other(u16),
 }
 
@@ -44,11 +71,28 @@ impl From<u16> for Operation {
         match raw {
             0x0001 => Self::Request,
             0x0002 => Self::Reply,
+            0x0003 => Self::ReverseRequest,
+            0x0004 => Self::ReverseReply,
+            0x0008 => Self::InverseRequest,
+            0x0009 => Self::InverseReply,
             other => Self::Other(other),
         }
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HardwareAddress {
+    Mac(MacAddress),
+    Other(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolAddress {
+    IPv4(Ipv4Addr),
+    IPv6(Ipv6Addr),
+    Other(Vec<u8>),
+}
+
 #[derive(Debug)]
 pub struct ArpPacket {
     pub hw_type: HardwareType,
@@ -56,24 +100,145 @@ pub struct ArpPacket {
     pub hw_size: u8,
     pub protocol_size: u8,
     pub operation: Operation,
-    pub src_mac: MacAddress,
-    pub src_addr: Ipv4Addr,
-    pub dest_mac: MacAddress,
-    pub dest_addr: Ipv4Addr,
+    pub src_hw_addr: HardwareAddress,
+    pub src_addr: ProtocolAddress,
+    pub dest_hw_addr: HardwareAddress,
+    pub dest_addr: ProtocolAddress,
+    // Bytes after the end of the packet, usually Ethernet padding up to the minimum frame size.
+    pub padding: usize,
+}
+
+fn hardware_address(bytes: &[u8]) -> HardwareAddress {
+    match MacAddress::try_from(bytes) {
+        Ok(mac) => HardwareAddress::Mac(mac),
+        Err(_) => HardwareAddress::Other(bytes.to_vec()),
+    }
 }
 
-pub fn parse_arp(input: &[u8]) -> Result<(&[u8], ArpPacket), TryFromSliceError> {
-    let hw_type =  HardwareType::from(u16::from_be_bytes(<[u8; 2]>::try_from(&input[0..2])?));
-    let protocol_type =  ProtocolType::from(u16::from_be_bytes(<[u8; 2]>::try_from(&input[2..4])?));
-    let hw_size = <u8>::try_from(input[4])?;
-    let protocol_size = <u8>::try_from(input[5])?;
-    let operation =  Operation::from(u16::from_be_bytes(<[u8; 2]>::try_from(&input[6..8])?));
+fn protocol_address(protocol_type: &ProtocolType, bytes: &[u8]) -> ProtocolAddress {
+    let address = match protocol_type {
+        ProtocolType::IPv4 => <[u8; 4]>::try_from(bytes).ok().map(|octets| ProtocolAddress::IPv4(Ipv4Addr::from(octets))),
+        ProtocolType::IPv6 => <[u8; 16]>::try_from(bytes).ok().map(|octets| ProtocolAddress::IPv6(Ipv6Addr::from(octets))),
+        ProtocolType::Other(_) => None,
+    };
+    address.unwrap_or_else(|| ProtocolAddress::Other(bytes.to_vec()))
+}
+
+// Address fields are sized by `hw_size` and `protocol_size`, and anything after
+// them is left as the remaining input.
+pub fn parse_arp(input: &[u8]) -> Result<(&[u8], ArpPacket), ParseError> {
+    ensure_length(input, 8)?;
+    let hw_type = HardwareType::from(read_u16(input, 0)?);
+    let protocol_type = ProtocolType::from(read_u16(input, 2)?);
+    let hw_size = read_u8(input, 4)?;
+    let protocol_size = read_u8(input, 5)?;
+    let operation = Operation::from(read_u16(input, 6)?);
+
+    let hw_length = hw_size as usize;
+    let protocol_length = protocol_size as usize;
+    let length = 8 + 2 * (hw_length + protocol_length);
+    ensure_length(input, length)?;
+    let (body, rest) = input[8..].split_at(length - 8);
+    let (src_hw_addr, body) = body.split_at(hw_length);
+    let (src_addr, body) = body.split_at(protocol_length);
+    let (dest_hw_addr, dest_addr) = body.split_at(hw_length);
+
+    let packet = ArpPacket {
+        src_hw_addr: hardware_address(src_hw_addr),
+        src_addr: protocol_address(&protocol_type, src_addr),
+        dest_hw_addr: hardware_address(dest_hw_addr),
+        dest_addr: protocol_address(&protocol_type, dest_addr),
+        hw_type,
+        protocol_type,
+        hw_size,
+        protocol_size,
+        operation,
+        padding: rest.len(),
+    };
+    Ok((rest, packet))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{dissect, LinkType};
+
+    fn arp(hw_type: u16, protocol_type: u16, operation: u16, hw_addr: &[u8], addr: &[u8]) -> Vec<u8> {
+        let mut bytes = hw_type.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&protocol_type.to_be_bytes());
+        bytes.extend_from_slice(&[hw_addr.len() as u8, addr.len() as u8]);
+        bytes.extend_from_slice(&operation.to_be_bytes());
+        for _ in 0..2 {
+            bytes.extend_from_slice(hw_addr);
+            bytes.extend_from_slice(addr);
+        }
+        bytes
+    }
+
+    const MAC: [u8; 6] = [0, 0x11, 0x22, 0x33, 0x44, 0x55];
+
+    #[test]
+    fn ethernet_ipv4_request() {
+        let bytes = arp(1, 0x0800, 1, &MAC, &[192, 168, 0, 1]);
+        let (rest, packet) = parse_arp(&bytes).unwrap();
+        assert!(rest.is_empty());
+        assert!(matches!(packet.hw_type, HardwareType::Ethernet));
+        assert!(matches!(packet.operation, Operation::Request));
+        assert_eq!(packet.src_hw_addr, HardwareAddress::Mac(MAC));
+        assert_eq!(packet.dest_addr, ProtocolAddress::IPv4(Ipv4Addr::new(192, 168, 0, 1)));
+        assert_eq!(packet.padding, 0);
+    }
+
+    #[test]
+    fn addresses_are_sized_by_the_header() {
+        // FireWire hardware addresses are 8 bytes long.
+        let eui64 = [1, 2, 3, 4, 5, 6, 7, 8];
+        let bytes = arp(0x18, 0x0800, 2, &eui64, &[10, 0, 0, 1]);
+        let (_, packet) = parse_arp(&bytes).unwrap();
+        assert!(matches!(packet.hw_type, HardwareType::Ieee1394));
+        assert_eq!(packet.dest_hw_addr, HardwareAddress::Other(eui64.to_vec()));
+        assert_eq!(packet.src_addr, ProtocolAddress::IPv4(Ipv4Addr::new(10, 0, 0, 1)));
+
+        let address: Ipv6Addr = "fe80::1".parse().unwrap();
+        let bytes = arp(1, 0x86DD, 1, &MAC, &address.octets());
+        let (_, packet) = parse_arp(&bytes).unwrap();
+        assert!(matches!(packet.protocol_type, ProtocolType::IPv6));
+        assert_eq!(packet.src_addr, ProtocolAddress::IPv6(address));
 
-    let src_mac = MacAddress::try_from(&input[8..14])?;
-    let src_addr = Ipv4Addr::from(<[u8; 4]>::try_from(&input[14..18])?);
-    let dest_mac = MacAddress::try_from(&input[18..24])?;
-    let dest_addr = Ipv4Addr::from(<[u8; 4]>::try_from(&input[24..28])?);
+        // A protocol address that does not fit its type is kept as bytes.
+        let bytes = arp(1, 0x0800, 1, &MAC, &[10, 0, 0]);
+        let (_, packet) = parse_arp(&bytes).unwrap();
+        assert_eq!(packet.src_addr, ProtocolAddress::Other(vec![10, 0, 0]));
+        let bytes = arp(1, 0x1234, 1, &MAC, &[1, 2]);
+        let (_, packet) = parse_arp(&bytes).unwrap();
+        assert_eq!(packet.dest_addr, ProtocolAddress::Other(vec![1, 2]));
 
-    let packet = ArpPacket { hw_type, protocol_type, hw_size, protocol_size, operation, src_mac, src_addr, dest_mac, dest_addr };
-    Ok((input, packet))
+        assert_eq!(parse_arp(&bytes[..15]).unwrap_err(), ParseError::Truncated { needed: 24, available: 15 });
+    }
+
+    #[test]
+    fn reverse_and_inverse_operations() {
+        for (raw, expected) in [(3, "ReverseRequest"), (4, "ReverseReply"), (8, "InverseRequest"), (9, "InverseReply"), (5, "Other(5)")] {
+            let bytes = arp(1, 0x0800, raw, &MAC, &[0; 4]);
+            let (_, packet) = parse_arp(&bytes).unwrap();
+            assert_eq!(format!("{:?}", packet.operation), expected);
+        }
+
+        // RARP is dissected from its own EtherType.
+        let mut frame = vec![0xff; 6];
+        frame.extend_from_slice(&MAC);
+        frame.extend_from_slice(&[0x80, 0x35]);
+        frame.extend(arp(1, 0x0800, 3, &MAC, &[0; 4]));
+        let packet = dissect(LinkType::Ethernet, &frame);
+        assert!(matches!(packet.arp().unwrap().operation, Operation::ReverseRequest));
+    }
+
+    #[test]
+    fn padding_after_the_packet() {
+        let mut bytes = arp(1, 0x0800, 2, &MAC, &[10, 0, 0, 1]);
+        bytes.extend_from_slice(&[0; 18]);
+        let (rest, packet) = parse_arp(&bytes).unwrap();
+        assert_eq!(packet.padding, 18);
+        assert_eq!(rest.len(), 18);
+    }
 }
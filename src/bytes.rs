@@ -0,0 +1,37 @@
+use crate::error::ParseError;
+
+pub(crate) fn ensure_length(input: &[u8], needed: usize) -> Result<(), ParseError> {
+    if input.len() < needed {
+        return Err(ParseError::Truncated { needed, available: input.len() });
+    }
+    Ok(())
+}
+
+pub(crate) fn skip(input: &[u8], count: usize) -> Result<&[u8], ParseError> {
+    ensure_length(input, count)?;
+    Ok(&input[count..])
+}
+
+pub(crate) fn read_array<const N: usize>(input: &[u8], offset: usize) -> Result<[u8; N], ParseError> {
+    let end = offset.saturating_add(N);
+    input
+        .get(offset..end)
+        .and_then(|bytes| <[u8; N]>::try_from(bytes).ok())
+        .ok_or(ParseError::Truncated { needed: end, available: input.len() })
+}
+
+pub(crate) fn read_u8(input: &[u8], offset: usize) -> Result<u8, ParseError> {
+    Ok(read_array::<1>(input, offset)?[0])
+}
+
+pub(crate) fn read_u16(input: &[u8], offset: usize) -> Result<u16, ParseError> {
+    Ok(u16::from_be_bytes(read_array(input, offset)?))
+}
+
+pub(crate) fn read_u32(input: &[u8], offset: usize) -> Result<u32, ParseError> {
+    Ok(u32::from_be_bytes(read_array(input, offset)?))
+}
+
+pub(crate) fn read_u64(input: &[u8], offset: usize) -> Result<u64, ParseError> {
+    Ok(u64::from_be_bytes(read_array(input, offset)?))
+}
@@ -0,0 +1,62 @@
+use std::net::IpAddr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumStatus {
+    Good,
+    Bad { expected: u16 },
+    Unverified,
+    NotPresent,
+}
+
+// RFC 1071: sum the data as big endian 16 bit words in one's complement
+// arithmetic, padding an odd trailing byte with zero.
+pub(crate) fn ones_complement_sum(initial: u32, data: &[u8]) -> u32 {
+    let mut sum = initial;
+    let mut words = data.chunks_exact(2);
+    for word in &mut words {
+        sum += u16::from_be_bytes([word[0], word[1]]) as u32;
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    if let [last] = words.remainder() {
+        sum += u16::from_be_bytes([*last, 0]) as u32;
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    sum
+}
+
+pub fn internet_checksum(data: &[u8]) -> u16 {
+    !(ones_complement_sum(0, data) as u16)
+}
+
+// `sum` covers the checksummed bytes including the transmitted checksum, which
+// folds to 0xFFFF when it is correct.
+pub(crate) fn checksum_status(sum: u32, transmitted: u16) -> ChecksumStatus {
+    if sum as u16 == 0xFFFF {
+        return ChecksumStatus::Good;
+    }
+    let without_checksum = ones_complement_sum(sum, &(!transmitted).to_be_bytes());
+    ChecksumStatus::Bad { expected: !(without_checksum as u16) }
+}
+
+pub(crate) fn pseudo_header_sum(source: IpAddr, dest: IpAddr, protocol: u8, length: usize) -> u32 {
+    match (source, dest) {
+        (IpAddr::V4(source), IpAddr::V4(dest)) => {
+            let sum = ones_complement_sum(0, &source.octets());
+            let sum = ones_complement_sum(sum, &dest.octets());
+            ones_complement_sum(sum, &[0, protocol, (length >> 8) as u8, length as u8])
+        }
+        (source, dest) => {
+            let sum = ones_complement_sum(0, &to_ipv6_octets(source));
+            let sum = ones_complement_sum(sum, &to_ipv6_octets(dest));
+            let sum = ones_complement_sum(sum, &(length as u32).to_be_bytes());
+            ones_complement_sum(sum, &[0, 0, 0, protocol])
+        }
+    }
+}
+
+fn to_ipv6_octets(address: IpAddr) -> [u8; 16] {
+    match address {
+        IpAddr::V4(address) => address.to_ipv6_mapped().octets(),
+        IpAddr::V6(address) => address.octets(),
+    }
+}
@@ -0,0 +1,146 @@
+use std::net::SocketAddr;
+use mohole::filter::DisplayFilter;
+use mohole::flow::Endpoint;
+use crate::follow::{FollowFormat, FollowOptions, StreamSelector};
+
+pub const USAGE: &str = "\
+Usage: mohole [OPTIONS] [EXPRESSION]
+
+Options:
+  -i, --interface <iface>   capture live packets from <iface>
+  -r, --read <file>         read packets from a pcap dump file
+  -c, --count <count>       stop after <count> packets
+  -s, --snaplen <bytes>     capture at most <bytes> of each packet (default 65535)
+  -p, --no-promiscuous      do not put the interface into promiscuous mode
+  -f, --filter <expr>       only capture packets matching the BPF expression <expr>
+  -Y, --display-filter <expr>
+                            only print packets matching the display filter <expr>
+  -T, --track-connections   print a record for each TCP connection as it ends
+  -z, --follow <stream>     print the payload of one TCP connection instead of the
+                            packets; <stream> is its index, counting from 0 in
+                            order of appearance, or <addr:port>,<addr:port>
+  --follow-format <format>  text (default), hex, or raw to write each side of the
+                            conversation to its own file
+  -o, --output <prefix>     name raw follow output <prefix>.client.bin and
+                            <prefix>.server.bin (default stream)
+  --metrics <prefix>        write per-connection RTT, goodput and bytes in flight
+                            to <prefix>.connections.csv, and every sample to
+                            <prefix>.samples.csv
+  -D, --list-interfaces     list the interfaces packets can be captured from
+  -h, --help                print this help message
+
+A trailing EXPRESSION is used as the capture filter, in tcpdump syntax.";
+
+#[derive(Debug)]
+pub enum Source {
+    Interface(String),
+    File(String),
+    DefaultInterface,
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub source: Source,
+    pub count: Option<usize>,
+    pub snaplen: i32,
+    pub promiscuous: bool,
+    pub capture_filter: Option<String>,
+    pub display_filter: Option<DisplayFilter>,
+    pub track_connections: bool,
+    pub follow: Option<FollowOptions>,
+    pub metrics: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum Command {
+    Capture(Box<Options>),
+    ListInterfaces,
+    Help,
+}
+
+fn value<I: Iterator<Item = String>>(args: &mut I, flag: &str) -> Result<String, String> {
+    args.next().ok_or(format!("option {} requires a value", flag))
+}
+
+fn number<T: std::str::FromStr, I: Iterator<Item = String>>(args: &mut I, flag: &str) -> Result<T, String> {
+    let raw = value(args, flag)?;
+    raw.parse().map_err(|_| format!("invalid value '{}' for option {}", raw, flag))
+}
+
+fn endpoint(raw: &str) -> Option<Endpoint> {
+    let address: SocketAddr = raw.trim().parse().ok()?;
+    Some(Endpoint { addr: address.ip(), port: address.port() })
+}
+
+fn stream_selector(raw: &str) -> Result<StreamSelector, String> {
+    if let Ok(index) = raw.parse() {
+        return Ok(StreamSelector::Index(index));
+    }
+    let endpoints = raw.split_once(',').and_then(|(a, b)| Some((endpoint(a)?, endpoint(b)?)));
+    match endpoints {
+        Some((a, b)) => Ok(StreamSelector::Endpoints(a, b)),
+        None => Err(format!("invalid stream '{}', expected an index or <addr:port>,<addr:port>", raw)),
+    }
+}
+
+pub fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+    let mut options = Options {
+        source: Source::DefaultInterface,
+        count: None,
+        snaplen: 65535,
+        promiscuous: true,
+        capture_filter: None,
+        display_filter: None,
+        track_connections: false,
+        follow: None,
+        metrics: None,
+    };
+    let mut follow: Option<StreamSelector> = None;
+    let mut follow_format = FollowFormat::Text;
+    let mut output = "stream".to_string();
+    let mut expression: Vec<String> = vec![];
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-i" | "--interface" => options.source = Source::Interface(value(&mut args, &arg)?),
+            "-r" | "--read" => options.source = Source::File(value(&mut args, &arg)?),
+            "-c" | "--count" => options.count = Some(number(&mut args, &arg)?),
+            "-s" | "--snaplen" => options.snaplen = number(&mut args, &arg)?,
+            "-p" | "--no-promiscuous" => options.promiscuous = false,
+            "-f" | "--filter" => options.capture_filter = Some(value(&mut args, &arg)?),
+            "-Y" | "--display-filter" => {
+                let expression = value(&mut args, &arg)?;
+                let filter = expression
+                    .parse()
+                    .map_err(|e| format!("invalid display filter '{}': {}", expression, e))?;
+                options.display_filter = Some(filter);
+            }
+            "-T" | "--track-connections" => options.track_connections = true,
+            "-z" | "--follow" => follow = Some(stream_selector(&value(&mut args, &arg)?)?),
+            "--follow-format" => {
+                follow_format = match value(&mut args, &arg)?.as_str() {
+                    "text" => FollowFormat::Text,
+                    "hex" => FollowFormat::Hex,
+                    "raw" => FollowFormat::Raw,
+                    other => return Err(format!("invalid value '{}' for option {}", other, arg)),
+                }
+            }
+            "-o" | "--output" => output = value(&mut args, &arg)?,
+            "--metrics" => options.metrics = Some(value(&mut args, &arg)?),
+            "-D" | "--list-interfaces" => return Ok(Command::ListInterfaces),
+            "-h" | "--help" => return Ok(Command::Help),
+            other if other.starts_with('-') => return Err(format!("unrecognized argument '{}'", other)),
+            _ => expression.push(arg),
+        }
+    }
+    if !expression.is_empty() {
+        if options.capture_filter.is_some() {
+            return Err("capture filter given both with -f and as a trailing expression".to_string());
+        }
+        options.capture_filter = Some(expression.join(" "));
+    }
+    options.follow = follow.map(|selector| FollowOptions { selector, format: follow_format, output });
+    if options.snaplen <= 0 {
+        return Err(format!("snaplen must be positive, got {}", options.snaplen));
+    }
+    Ok(Command::Capture(Box::new(options)))
+}
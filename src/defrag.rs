@@ -0,0 +1,487 @@
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use crate::ipv4::IPv4Datagram;
+
+const MAX_DATAGRAM_LENGTH: usize = 65535;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FragmentKey {
+    pub source_addr: Ipv4Addr,
+    pub dest_addr: Ipv4Addr,
+    pub protocol: u8,
+    pub id: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverlapPolicy {
+    First,
+    Last,
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DefragConfig {
+    pub timeout: Duration,
+    pub max_buffered_bytes: usize,
+    pub overlap_policy: OverlapPolicy,
+}
+
+impl Default for DefragConfig {
+    fn default() -> Self {
+        DefragConfig {
+            timeout: Duration::from_secs(30),
+            max_buffered_bytes: 4 * 1024 * 1024,
+            overlap_policy: OverlapPolicy::First,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FragmentAnomaly {
+    Overlap,
+    InconsistentOverlap,
+    Teardrop,
+    Oversized,
+    UnalignedFragment,
+    ConflictingLength,
+    Truncated,
+    MemoryLimit,
+}
+
+#[derive(Debug)]
+pub struct Reassembled {
+    pub datagram: IPv4Datagram,
+    pub payload: Vec<u8>,
+    pub fragments: usize,
+    pub anomalies: Vec<FragmentAnomaly>,
+}
+
+#[derive(Debug)]
+pub enum DefragResult {
+    NotFragmented,
+    Incomplete,
+    Complete(Reassembled),
+    Dropped(FragmentAnomaly),
+}
+
+#[derive(Debug)]
+struct PendingDatagram {
+    first_seen: Duration,
+    header: Option<IPv4Datagram>,
+    data: Vec<u8>,
+    // Sorted, non-overlapping byte ranges of `data` received so far.
+    ranges: Vec<(usize, usize)>,
+    total_length: Option<usize>,
+    fragments: usize,
+    anomalies: Vec<FragmentAnomaly>,
+}
+
+impl PendingDatagram {
+    fn is_complete(&self) -> bool {
+        match self.total_length {
+            Some(total_length) => self.ranges == [(0, total_length)],
+            None => false,
+        }
+    }
+
+    fn insert_range(&mut self, start: usize, end: usize) {
+        self.ranges.push((start, end));
+        self.ranges.sort_unstable();
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(self.ranges.len());
+        for &(start, end) in &self.ranges {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        self.ranges = merged;
+    }
+
+    // Copies the fragment into the buffer and returns the anomalies it caused,
+    // or the anomaly that makes the whole datagram unusable.
+    fn add(&mut self, start: usize, data: &[u8], policy: OverlapPolicy) -> Result<Vec<FragmentAnomaly>, FragmentAnomaly> {
+        let end = start + data.len();
+        let mut anomalies: Vec<FragmentAnomaly> = vec![];
+        let overlapping: Vec<(usize, usize)> = self
+            .ranges
+            .iter()
+            .filter(|&&(range_start, range_end)| range_start < end && start < range_end)
+            .map(|&(range_start, range_end)| (range_start.max(start), range_end.min(end)))
+            .collect();
+        if !overlapping.is_empty() {
+            if policy == OverlapPolicy::Reject {
+                return Err(FragmentAnomaly::Overlap);
+            }
+            anomalies.push(FragmentAnomaly::Overlap);
+            let consistent = overlapping
+                .iter()
+                .all(|&(overlap_start, overlap_end)| {
+                    self.data[overlap_start..overlap_end] == data[overlap_start - start..overlap_end - start]
+                });
+            if !consistent {
+                anomalies.push(FragmentAnomaly::InconsistentOverlap);
+                // A fragment that lies entirely inside data we already have, with
+                // different contents, is the shape of the teardrop attack. An
+                // identical copy is just a retransmitted fragment.
+                if overlapping == [(start, end)] {
+                    anomalies.push(FragmentAnomaly::Teardrop);
+                }
+            }
+        }
+        if self.data.len() < end {
+            self.data.resize(end, 0);
+        }
+        match policy {
+            OverlapPolicy::Last => self.data[start..end].copy_from_slice(data),
+            _ => {
+                let mut position = start;
+                for &(overlap_start, overlap_end) in overlapping.iter().chain([(end, end)].iter()) {
+                    if position < overlap_start {
+                        self.data[position..overlap_start].copy_from_slice(&data[position - start..overlap_start - start]);
+                    }
+                    position = position.max(overlap_end);
+                }
+            }
+        }
+        self.insert_range(start, end);
+        Ok(anomalies)
+    }
+}
+
+#[derive(Debug)]
+pub struct Defragmenter {
+    config: DefragConfig,
+    pending: HashMap<FragmentKey, PendingDatagram>,
+    buffered_bytes: usize,
+}
+
+impl Defragmenter {
+    pub fn new(config: DefragConfig) -> Self {
+        Defragmenter { config, pending: HashMap::new(), buffered_bytes: 0 }
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn remove(&mut self, key: &FragmentKey) -> Option<PendingDatagram> {
+        let pending = self.pending.remove(key)?;
+        self.buffered_bytes -= pending.data.len();
+        Some(pending)
+    }
+
+    pub fn expire(&mut self, now: Duration) -> Vec<FragmentKey> {
+        let timeout = self.config.timeout;
+        let expired: Vec<FragmentKey> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| now.saturating_sub(pending.first_seen) > timeout)
+            .map(|(key, _)| *key)
+            .collect();
+        for key in &expired {
+            self.remove(key);
+        }
+        expired
+    }
+
+    pub fn process(&mut self, now: Duration, datagram: &IPv4Datagram, payload: &[u8]) -> DefragResult {
+        if !datagram.is_fragment() {
+            return DefragResult::NotFragmented;
+        }
+        self.expire(now);
+        let key = FragmentKey {
+            source_addr: datagram.source_addr,
+            dest_addr: datagram.dest_addr,
+            protocol: u8::from(datagram.protocol),
+            id: datagram.id,
+        };
+        let more_fragments = datagram.flags & 0b001 != 0;
+        let start = datagram.fragment_offset as usize * 8;
+        let length = (datagram.length as usize).saturating_sub(datagram.header_length as usize * 4);
+        let data = match payload.get(..length) {
+            Some(data) => data,
+            None => {
+                self.remove(&key);
+                return DefragResult::Dropped(FragmentAnomaly::Truncated);
+            }
+        };
+        let end = start + data.len();
+        if datagram.header_length as usize * 4 + end > MAX_DATAGRAM_LENGTH {
+            self.remove(&key);
+            return DefragResult::Dropped(FragmentAnomaly::Oversized);
+        }
+
+        let pending = self.pending.entry(key).or_insert_with(|| PendingDatagram {
+            first_seen: now,
+            header: None,
+            data: vec![],
+            ranges: vec![],
+            total_length: None,
+            fragments: 0,
+            anomalies: vec![],
+        });
+        let buffered_before = pending.data.len();
+        let mut anomalies: Vec<FragmentAnomaly> = vec![];
+        if more_fragments && !data.len().is_multiple_of(8) {
+            anomalies.push(FragmentAnomaly::UnalignedFragment);
+        }
+        let conflicting_length = match pending.total_length {
+            Some(total_length) => end > total_length || (!more_fragments && end != total_length),
+            None => !more_fragments && pending.data.len() > end,
+        };
+        let result = if conflicting_length {
+            Err(FragmentAnomaly::ConflictingLength)
+        } else if self.buffered_bytes + end.saturating_sub(buffered_before) > self.config.max_buffered_bytes {
+            Err(FragmentAnomaly::MemoryLimit)
+        } else {
+            pending.add(start, data, self.config.overlap_policy)
+        };
+        self.buffered_bytes += pending.data.len() - buffered_before;
+        match result {
+            Ok(added) => anomalies.extend(added),
+            Err(anomaly) => {
+                self.remove(&key);
+                return DefragResult::Dropped(anomaly);
+            }
+        }
+
+        pending.fragments += 1;
+        pending.anomalies.extend(anomalies);
+        if !more_fragments {
+            pending.total_length = Some(end);
+        }
+        if start == 0 && pending.header.is_none() {
+            pending.header = Some(datagram.clone());
+        }
+        if !pending.is_complete() || pending.header.is_none() {
+            return DefragResult::Incomplete;
+        }
+
+        match self.remove(&key) {
+            Some(PendingDatagram { header: Some(mut header), data, fragments, anomalies, .. }) => {
+                header.length = (header.header_length as usize * 4 + data.len()) as u16;
+                header.flags &= !0b001;
+                header.fragment_offset = 0;
+                DefragResult::Complete(Reassembled { datagram: header, payload: data, fragments, anomalies })
+            }
+            _ => DefragResult::Incomplete,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::ChecksumStatus;
+    use crate::ipv4::{Ecn, IPType};
+
+    fn fragment(offset: usize, length: usize, more_fragments: bool) -> IPv4Datagram {
+        IPv4Datagram {
+            version: 4,
+            header_length: 5,
+            dscp: 0,
+            ecn: Ecn::NotEct,
+            length: (20 + length) as u16,
+            id: 0x1234,
+            flags: more_fragments as u8,
+            fragment_offset: (offset / 8) as u16,
+            ttl: 64,
+            protocol: IPType::UDP,
+            header_checksum: 0,
+            checksum_status: ChecksumStatus::Unverified,
+            source_addr: Ipv4Addr::new(10, 0, 0, 1),
+            dest_addr: Ipv4Addr::new(10, 0, 0, 2),
+            options: None,
+        }
+    }
+
+    fn payload(length: usize) -> Vec<u8> {
+        (0..length).map(|i| i as u8).collect()
+    }
+
+    // Feeds the byte range `start..end` of `data` to the defragmenter.
+    fn add(defrag: &mut Defragmenter, now: u64, data: &[u8], start: usize, end: usize, more: bool) -> DefragResult {
+        defrag.process(Duration::from_secs(now), &fragment(start, end - start, more), &data[start..end])
+    }
+
+    fn complete(result: DefragResult) -> Reassembled {
+        match result {
+            DefragResult::Complete(reassembled) => reassembled,
+            other => panic!("expected a complete datagram, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unfragmented_datagrams_pass_through() {
+        let mut defrag = Defragmenter::new(DefragConfig::default());
+        let data = payload(16);
+        assert!(matches!(add(&mut defrag, 0, &data, 0, 16, false), DefragResult::NotFragmented));
+        assert_eq!(defrag.pending(), 0);
+    }
+
+    #[test]
+    fn reassembles_out_of_order_fragments() {
+        let mut defrag = Defragmenter::new(DefragConfig::default());
+        let data = payload(40);
+        assert!(matches!(add(&mut defrag, 0, &data, 32, 40, false), DefragResult::Incomplete));
+        assert!(matches!(add(&mut defrag, 0, &data, 16, 32, true), DefragResult::Incomplete));
+        let reassembled = complete(add(&mut defrag, 1, &data, 0, 16, true));
+        assert_eq!(reassembled.payload, data);
+        assert_eq!(reassembled.fragments, 3);
+        assert!(reassembled.anomalies.is_empty());
+        assert_eq!(reassembled.datagram.length, 60);
+        assert!(!reassembled.datagram.is_fragment());
+        assert_eq!(defrag.pending(), 0);
+    }
+
+    #[test]
+    fn duplicate_fragments_are_only_an_overlap() {
+        let mut defrag = Defragmenter::new(DefragConfig::default());
+        let data = payload(24);
+        add(&mut defrag, 0, &data, 0, 16, true);
+        add(&mut defrag, 0, &data, 0, 16, true);
+        let reassembled = complete(add(&mut defrag, 0, &data, 16, 24, false));
+        assert_eq!(reassembled.payload, data);
+        assert_eq!(reassembled.fragments, 3);
+        assert_eq!(reassembled.anomalies, [FragmentAnomaly::Overlap]);
+    }
+
+    #[test]
+    fn consistent_partial_overlap() {
+        let mut defrag = Defragmenter::new(DefragConfig::default());
+        let data = payload(32);
+        add(&mut defrag, 0, &data, 0, 16, true);
+        let reassembled = complete(add(&mut defrag, 0, &data, 8, 32, false));
+        assert_eq!(reassembled.payload, data);
+        assert_eq!(reassembled.anomalies, [FragmentAnomaly::Overlap]);
+    }
+
+    // Sends a fragment that lies inside the first one with different bytes,
+    // then the last fragment.
+    fn teardrop(policy: OverlapPolicy) -> (DefragResult, DefragResult) {
+        let config = DefragConfig { overlap_policy: policy, ..DefragConfig::default() };
+        let mut defrag = Defragmenter::new(config);
+        let data = payload(32);
+        let mut forged = data.clone();
+        forged[8..16].fill(0xFF);
+        add(&mut defrag, 0, &data, 0, 24, true);
+        let contained = add(&mut defrag, 0, &forged, 8, 16, true);
+        (contained, add(&mut defrag, 0, &data, 24, 32, false))
+    }
+
+    #[test]
+    fn contained_fragment_with_different_bytes_is_teardrop() {
+        let reassembled = complete(teardrop(OverlapPolicy::First).1);
+        assert_eq!(
+            reassembled.anomalies,
+            [FragmentAnomaly::Overlap, FragmentAnomaly::InconsistentOverlap, FragmentAnomaly::Teardrop]
+        );
+        assert_eq!(reassembled.payload, payload(32));
+    }
+
+    #[test]
+    fn last_policy_keeps_the_newer_bytes() {
+        let reassembled = complete(teardrop(OverlapPolicy::Last).1);
+        let mut expected = payload(32);
+        expected[8..16].fill(0xFF);
+        assert_eq!(reassembled.payload, expected);
+    }
+
+    #[test]
+    fn reject_policy_drops_overlapping_datagrams() {
+        let (contained, last) = teardrop(OverlapPolicy::Reject);
+        assert!(matches!(contained, DefragResult::Dropped(FragmentAnomaly::Overlap)));
+        assert!(matches!(last, DefragResult::Incomplete));
+    }
+
+    #[test]
+    fn drops_datagrams_over_the_memory_limit() {
+        let config = DefragConfig { max_buffered_bytes: 32, ..DefragConfig::default() };
+        let mut defrag = Defragmenter::new(config);
+        let data = payload(48);
+        assert!(matches!(add(&mut defrag, 0, &data, 0, 24, true), DefragResult::Incomplete));
+        assert!(matches!(
+            add(&mut defrag, 0, &data, 24, 40, true),
+            DefragResult::Dropped(FragmentAnomaly::MemoryLimit)
+        ));
+        assert_eq!(defrag.pending(), 0);
+        // The dropped datagram's buffer is released.
+        assert!(matches!(add(&mut defrag, 0, &data, 0, 24, true), DefragResult::Incomplete));
+    }
+
+    #[test]
+    fn drops_datagrams_with_conflicting_lengths() {
+        let mut defrag = Defragmenter::new(DefragConfig::default());
+        let data = payload(32);
+        add(&mut defrag, 0, &data, 16, 24, false);
+        assert!(matches!(
+            add(&mut defrag, 0, &data, 16, 32, true),
+            DefragResult::Dropped(FragmentAnomaly::ConflictingLength)
+        ));
+        assert_eq!(defrag.pending(), 0);
+
+        // A last fragment that ends before data already received.
+        add(&mut defrag, 0, &data, 16, 32, true);
+        assert!(matches!(
+            add(&mut defrag, 0, &data, 8, 16, false),
+            DefragResult::Dropped(FragmentAnomaly::ConflictingLength)
+        ));
+    }
+
+    #[test]
+    fn drops_oversized_datagrams() {
+        let mut defrag = Defragmenter::new(DefragConfig::default());
+        let data = vec![0; 32];
+        let datagram = fragment(65512, 32, false);
+        assert!(matches!(
+            defrag.process(Duration::ZERO, &datagram, &data),
+            DefragResult::Dropped(FragmentAnomaly::Oversized)
+        ));
+        assert_eq!(defrag.pending(), 0);
+    }
+
+    #[test]
+    fn drops_truncated_fragments() {
+        let mut defrag = Defragmenter::new(DefragConfig::default());
+        let data = payload(16);
+        let datagram = fragment(0, 24, true);
+        assert!(matches!(
+            defrag.process(Duration::ZERO, &datagram, &data),
+            DefragResult::Dropped(FragmentAnomaly::Truncated)
+        ));
+    }
+
+    #[test]
+    fn flags_unaligned_fragments() {
+        let mut defrag = Defragmenter::new(DefragConfig::default());
+        let data = payload(20);
+        add(&mut defrag, 0, &data, 0, 12, true);
+        let reassembled = complete(add(&mut defrag, 0, &data, 8, 20, false));
+        assert_eq!(reassembled.payload, data);
+        assert_eq!(reassembled.anomalies, [FragmentAnomaly::UnalignedFragment, FragmentAnomaly::Overlap]);
+    }
+
+    #[test]
+    fn expires_incomplete_datagrams() {
+        let mut defrag = Defragmenter::new(DefragConfig::default());
+        let data = payload(24);
+        add(&mut defrag, 0, &data, 0, 16, true);
+        assert!(defrag.expire(Duration::from_secs(30)).is_empty());
+        let expired = defrag.expire(Duration::from_secs(31));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].id, 0x1234);
+        assert_eq!(defrag.pending(), 0);
+        // The rest of the datagram arrives too late to complete it.
+        assert!(matches!(add(&mut defrag, 32, &data, 16, 24, false), DefragResult::Incomplete));
+    }
+
+    #[test]
+    fn processing_expires_old_fragments() {
+        let mut defrag = Defragmenter::new(DefragConfig::default());
+        let data = payload(24);
+        add(&mut defrag, 0, &data, 0, 16, true);
+        assert!(matches!(add(&mut defrag, 40, &data, 16, 24, false), DefragResult::Incomplete));
+        assert_eq!(defrag.pending(), 1);
+    }
+}
@@ -0,0 +1,29 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    Truncated { needed: usize, available: usize },
+    InvalidValue { field: &'static str, value: u32 },
+    BadHeaderLength { protocol: &'static str, length: usize },
+    BadOption { kind: u8, reason: &'static str },
+}
+
+impl Error for ParseError {}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Truncated { needed, available } => {
+                write!(f, "truncated input: needed {} bytes, only {} available", needed, available)
+            }
+            Self::InvalidValue { field, value } => {
+                write!(f, "invalid value {:#x} for field {}", value, field)
+            }
+            Self::BadHeaderLength { protocol, length } => {
+                write!(f, "bad {} header length: {} bytes", protocol, length)
+            }
+            Self::BadOption { kind, reason } => write!(f, "bad option of kind {}: {}", kind, reason),
+        }
+    }
+}
@@ -1,19 +1,30 @@
-use std::array::TryFromSliceError;
+use crate::bytes::{ensure_length, read_array, read_u16};
+use crate::error::ParseError;
 
 pub type MacAddress = [u8;6];
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EtherType {
     IPv4,
     IPv6,
     ARP,
+    RARP,
     Other(u16),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VlanTag {
+    pub tpid: u16,
+    pub pcp: u8,
+    pub dei: bool,
+    pub vid: u16,
+}
+
 #[derive(Debug)]
 pub struct EthernetFrame {
     pub source_mac: MacAddress,
     pub dest_mac: MacAddress,
+    pub vlan_tags: Vec<VlanTag>,
     pub ethertype: EtherType,
 }
 
@@ -23,24 +34,41 @@ impl From<u16> for EtherType {
             0x0800 => Self::IPv4,           
             0x86DD => Self::IPv6, 
             0x0806 => Self::ARP,            
+            0x8035 => Self::RARP,
             other => Self::Other(other),
         }
     }
 }
 
-pub fn parse_ethernet(input: &[u8]) -> Result<(&[u8], EthernetFrame), TryFromSliceError> {
-    let dest_mac = MacAddress::try_from(&input[0..6])?;
+pub fn parse_ethernet(input: &[u8]) -> Result<(&[u8], EthernetFrame), ParseError> {
+    ensure_length(input, 14)?;
+    let dest_mac: MacAddress = read_array(input, 0)?;
 
-    let source_mac = MacAddress::try_from(&input[6..12])?;
+    let source_mac: MacAddress = read_array(input, 6)?;
 
-    let ether_type_bytes = <[u8; 2]>::try_from(&input[12..14])?;
+    let mut ether_type = read_u16(input, 12)?;
+    let mut header_length = 14;
+
+    let mut vlan_tags: Vec<VlanTag> = vec![];
+    while matches!(ether_type, 0x8100 | 0x88A8 | 0x9100) {
+        let tci = read_u16(input, header_length)?;
+        vlan_tags.push(VlanTag {
+            tpid: ether_type,
+            pcp: (tci >> 13) as u8,
+            dei: tci & 0x1000 != 0,
+            vid: tci & 0x0FFF,
+        });
+        ether_type = read_u16(input, header_length + 2)?;
+        header_length += 4;
+    }
 
-    let (_, input) = input.split_at(14);
+    let (_, input) = input.split_at(header_length);
 
-    let ethertype: EtherType = EtherType::from(u16::from_be_bytes(ether_type_bytes));
+    let ethertype: EtherType = EtherType::from(ether_type);
     let frame = EthernetFrame {
         dest_mac,
         source_mac,
+        vlan_tags,
         ethertype
     };
     Ok((input, frame))
@@ -0,0 +1,831 @@
+use std::error::Error;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use crate::arp::{HardwareAddress, HardwareType, Operation, ProtocolAddress, ProtocolType};
+use crate::checksum::ChecksumStatus;
+use crate::ethernet::{EtherType, MacAddress, VlanTag};
+use crate::icmp::IcmpMessage;
+use crate::ipv4::{Ecn, IPType};
+use crate::packet::Packet;
+use crate::tcp::TcpFlags;
+use crate::udp::UdpLengthStatus;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterError {
+    UnexpectedEnd,
+    UnexpectedToken(String),
+    UnknownField(String),
+    InvalidValue(String),
+}
+
+impl Error for FilterError {}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnexpectedEnd => write!(f, "unexpected end of filter expression"),
+            Self::UnexpectedToken(token) => write!(f, "unexpected '{}' in filter expression", token),
+            Self::UnknownField(name) => write!(f, "unknown field '{}'", name),
+            Self::InvalidValue(value) => write!(f, "invalid value '{}'", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value {
+    Bool(bool),
+    Integer(u64),
+    Ipv4(Ipv4Addr),
+    Ipv6(Ipv6Addr),
+    Mac(MacAddress),
+    Named(&'static str, u64),
+}
+
+pub struct Field {
+    pub name: &'static str,
+    pub extract: fn(&Packet) -> Option<Value>,
+}
+
+impl fmt::Debug for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Field {{ name: {} }}", self.name)
+    }
+}
+
+fn ethertype_value(ethertype: EtherType) -> Value {
+    match ethertype {
+        EtherType::IPv4 => Value::Named("ipv4", 0x0800),
+        EtherType::IPv6 => Value::Named("ipv6", 0x86DD),
+        EtherType::ARP => Value::Named("arp", 0x0806),
+        EtherType::RARP => Value::Named("rarp", 0x8035),
+        EtherType::Other(raw) => Value::Integer(raw as u64),
+    }
+}
+
+fn ip_type_value(protocol: IPType) -> Value {
+    match protocol {
+        IPType::ICMP => Value::Named("icmp", 1),
+        IPType::TCP => Value::Named("tcp", 6),
+        IPType::UDP => Value::Named("udp", 17),
+        IPType::ICMPv6 => Value::Named("icmpv6", 58),
+        IPType::Other(raw) => Value::Integer(raw as u64),
+    }
+}
+
+fn ecn_value(ecn: Ecn) -> Value {
+    match ecn {
+        Ecn::NotEct => Value::Named("not_ect", 0),
+        Ecn::Ect1 => Value::Named("ect1", 1),
+        Ecn::Ect0 => Value::Named("ect0", 2),
+        Ecn::CongestionExperienced => Value::Named("ce", 3),
+    }
+}
+
+fn hardware_type_value(hw_type: &HardwareType) -> Value {
+    match hw_type {
+        HardwareType::Ethernet => Value::Named("ethernet", 1),
+        HardwareType::Ieee802 => Value::Named("ieee802", 6),
+        HardwareType::Arcnet => Value::Named("arcnet", 7),
+        HardwareType::FrameRelay => Value::Named("frame_relay", 15),
+        HardwareType::Atm => Value::Named("atm", 16),
+        HardwareType::Hdlc => Value::Named("hdlc", 17),
+        HardwareType::FibreChannel => Value::Named("fibre_channel", 18),
+        HardwareType::Serial => Value::Named("serial", 20),
+        HardwareType::Ieee1394 => Value::Named("ieee1394", 24),
+        HardwareType::InfiniBand => Value::Named("infiniband", 32),
+        HardwareType::Other(raw) => Value::Integer(*raw as u64),
+    }
+}
+
+fn protocol_type_value(protocol_type: &ProtocolType) -> Value {
+    match protocol_type {
+        ProtocolType::IPv4 => Value::Named("ipv4", 0x0800),
+        ProtocolType::IPv6 => Value::Named("ipv6", 0x86DD),
+        ProtocolType::Other(raw) => Value::Integer(*raw as u64),
+    }
+}
+
+fn operation_value(operation: &Operation) -> Value {
+    match operation {
+        Operation::Request => Value::Named("request", 1),
+        Operation::Reply => Value::Named("reply", 2),
+        Operation::ReverseRequest => Value::Named("reverse_request", 3),
+        Operation::ReverseReply => Value::Named("reverse_reply", 4),
+        Operation::InverseRequest => Value::Named("inverse_request", 8),
+        Operation::InverseReply => Value::Named("inverse_reply", 9),
+        Operation::Other(raw) => Value::Integer(*raw as u64),
+    }
+}
+
+fn hardware_address_value(address: &HardwareAddress) -> Option<Value> {
+    match address {
+        HardwareAddress::Mac(mac) => Some(Value::Mac(*mac)),
+        HardwareAddress::Other(_) => None,
+    }
+}
+
+fn protocol_address_value(address: &ProtocolAddress) -> Option<Value> {
+    match address {
+        ProtocolAddress::IPv4(address) => Some(Value::Ipv4(*address)),
+        ProtocolAddress::IPv6(address) => Some(Value::Ipv6(*address)),
+        ProtocolAddress::Other(_) => None,
+    }
+}
+
+fn checksum_status_value(status: ChecksumStatus) -> Value {
+    match status {
+        ChecksumStatus::Good => Value::Named("good", 0),
+        ChecksumStatus::Bad { .. } => Value::Named("bad", 1),
+        ChecksumStatus::Unverified => Value::Named("unverified", 2),
+        ChecksumStatus::NotPresent => Value::Named("not_present", 3),
+    }
+}
+
+fn udp_length_status_value(status: UdpLengthStatus) -> Value {
+    match status {
+        UdpLengthStatus::Good => Value::Named("good", 0),
+        UdpLengthStatus::TooShort => Value::Named("too_short", 1),
+        UdpLengthStatus::Truncated { .. } => Value::Named("truncated", 2),
+        UdpLengthStatus::IpMismatch { .. } => Value::Named("ip_mismatch", 3),
+    }
+}
+
+fn outer_vlan_tag<'a>(packet: &'a Packet) -> Option<&'a VlanTag> {
+    packet.ethernet().and_then(|frame| frame.vlan_tags.first())
+}
+
+fn icmp_id_sequence(packet: &Packet) -> Option<(u16, u16)> {
+    match packet.icmp()?.message {
+        IcmpMessage::Echo { identifier, sequence_no } | IcmpMessage::Timestamp { identifier, sequence_no, .. } => {
+            Some((identifier, sequence_no))
+        }
+        IcmpMessage::ExtendedEchoRequest { identifier, sequence_no, .. }
+        | IcmpMessage::ExtendedEchoReply { identifier, sequence_no, .. } => Some((identifier, sequence_no as u16)),
+        _ => None,
+    }
+}
+
+macro_rules! field {
+    ($name:expr, $layer:ident, |$x:ident| $value:expr) => {
+        Field { name: $name, extract: |packet| packet.$layer().map(|$x| $value) }
+    };
+}
+
+pub static FIELDS: &[Field] = &[
+    field!("eth", ethernet, |_frame| Value::Bool(true)),
+    field!("eth.source_mac", ethernet, |frame| Value::Mac(frame.source_mac)),
+    field!("eth.dest_mac", ethernet, |frame| Value::Mac(frame.dest_mac)),
+    field!("eth.ethertype", ethernet, |frame| ethertype_value(frame.ethertype)),
+    Field { name: "vlan", extract: |packet| outer_vlan_tag(packet).map(|_tag| Value::Bool(true)) },
+    Field { name: "vlan.tpid", extract: |packet| outer_vlan_tag(packet).map(|tag| Value::Integer(tag.tpid as u64)) },
+    Field { name: "vlan.pcp", extract: |packet| outer_vlan_tag(packet).map(|tag| Value::Integer(tag.pcp as u64)) },
+    Field { name: "vlan.dei", extract: |packet| outer_vlan_tag(packet).map(|tag| Value::Bool(tag.dei)) },
+    Field { name: "vlan.vid", extract: |packet| outer_vlan_tag(packet).map(|tag| Value::Integer(tag.vid as u64)) },
+    field!("ipv4", ipv4, |_datagram| Value::Bool(true)),
+    field!("ipv4.version", ipv4, |datagram| Value::Integer(datagram.version as u64)),
+    field!("ipv4.header_length", ipv4, |datagram| Value::Integer(datagram.header_length as u64)),
+    field!("ipv4.dscp", ipv4, |datagram| Value::Integer(datagram.dscp as u64)),
+    field!("ipv4.ecn", ipv4, |datagram| ecn_value(datagram.ecn)),
+    field!("ipv4.length", ipv4, |datagram| Value::Integer(datagram.length as u64)),
+    field!("ipv4.id", ipv4, |datagram| Value::Integer(datagram.id as u64)),
+    field!("ipv4.flags", ipv4, |datagram| Value::Integer(datagram.flags as u64)),
+    field!("ipv4.fragment_offset", ipv4, |datagram| Value::Integer(datagram.fragment_offset as u64)),
+    field!("ipv4.ttl", ipv4, |datagram| Value::Integer(datagram.ttl as u64)),
+    field!("ipv4.protocol", ipv4, |datagram| ip_type_value(datagram.protocol)),
+    field!("ipv4.header_checksum", ipv4, |datagram| Value::Integer(datagram.header_checksum as u64)),
+    field!("ipv4.checksum_status", ipv4, |datagram| checksum_status_value(datagram.checksum_status)),
+    field!("ipv4.source_addr", ipv4, |datagram| Value::Ipv4(datagram.source_addr)),
+    field!("ipv4.dest_addr", ipv4, |datagram| Value::Ipv4(datagram.dest_addr)),
+    field!("ipv6", ipv6, |_datagram| Value::Bool(true)),
+    field!("ipv6.version", ipv6, |datagram| Value::Integer(datagram.version as u64)),
+    field!("ipv6.dscp", ipv6, |datagram| Value::Integer(datagram.dscp as u64)),
+    field!("ipv6.ecn", ipv6, |datagram| ecn_value(datagram.ecn)),
+    field!("ipv6.flow_label", ipv6, |datagram| Value::Integer(datagram.flow_label as u64)),
+    field!("ipv6.payload_length", ipv6, |datagram| Value::Integer(datagram.payload_length as u64)),
+    field!("ipv6.next_header", ipv6, |datagram| ip_type_value(IPType::from(datagram.next_header))),
+    field!("ipv6.hop_limit", ipv6, |datagram| Value::Integer(datagram.hop_limit as u64)),
+    field!("ipv6.source_addr", ipv6, |datagram| Value::Ipv6(datagram.source_addr)),
+    field!("ipv6.dest_addr", ipv6, |datagram| Value::Ipv6(datagram.dest_addr)),
+    field!("ipv6.protocol", ipv6, |datagram| ip_type_value(datagram.protocol)),
+    field!("arp", arp, |_packet| Value::Bool(true)),
+    field!("arp.hw_type", arp, |packet| hardware_type_value(&packet.hw_type)),
+    field!("arp.protocol_type", arp, |packet| protocol_type_value(&packet.protocol_type)),
+    field!("arp.hw_size", arp, |packet| Value::Integer(packet.hw_size as u64)),
+    field!("arp.protocol_size", arp, |packet| Value::Integer(packet.protocol_size as u64)),
+    field!("arp.operation", arp, |packet| operation_value(&packet.operation)),
+    Field { name: "arp.src_mac", extract: |packet| packet.arp().and_then(|packet| hardware_address_value(&packet.src_hw_addr)) },
+    Field { name: "arp.src_addr", extract: |packet| packet.arp().and_then(|packet| protocol_address_value(&packet.src_addr)) },
+    Field { name: "arp.dest_mac", extract: |packet| packet.arp().and_then(|packet| hardware_address_value(&packet.dest_hw_addr)) },
+    Field { name: "arp.dest_addr", extract: |packet| packet.arp().and_then(|packet| protocol_address_value(&packet.dest_addr)) },
+    field!("arp.padding", arp, |packet| Value::Integer(packet.padding as u64)),
+    field!("tcp", tcp, |_segment| Value::Bool(true)),
+    field!("tcp.source_port", tcp, |segment| Value::Integer(segment.source_port as u64)),
+    field!("tcp.dest_port", tcp, |segment| Value::Integer(segment.dest_port as u64)),
+    field!("tcp.sequence_no", tcp, |segment| Value::Integer(segment.sequence_no as u64)),
+    field!("tcp.ack_no", tcp, |segment| Value::Integer(segment.ack_no as u64)),
+    field!("tcp.header_length", tcp, |segment| Value::Integer(segment.header_length as u64)),
+    field!("tcp.reserved", tcp, |segment| Value::Integer(segment.reserved as u64)),
+    field!("tcp.flags", tcp, |segment| Value::Integer(segment.flags.bits() as u64)),
+    field!("tcp.flag_ns", tcp, |segment| Value::Bool(segment.flags.contains(TcpFlags::NS))),
+    field!("tcp.flag_cwr", tcp, |segment| Value::Bool(segment.flags.contains(TcpFlags::CWR))),
+    field!("tcp.flag_ece", tcp, |segment| Value::Bool(segment.flags.contains(TcpFlags::ECE))),
+    field!("tcp.flag_urg", tcp, |segment| Value::Bool(segment.flags.contains(TcpFlags::URG))),
+    field!("tcp.flag_ack", tcp, |segment| Value::Bool(segment.flags.contains(TcpFlags::ACK))),
+    field!("tcp.flag_psh", tcp, |segment| Value::Bool(segment.flags.contains(TcpFlags::PSH))),
+    field!("tcp.flag_rst", tcp, |segment| Value::Bool(segment.flags.contains(TcpFlags::RST))),
+    field!("tcp.flag_syn", tcp, |segment| Value::Bool(segment.flags.contains(TcpFlags::SYN))),
+    field!("tcp.flag_fin", tcp, |segment| Value::Bool(segment.flags.contains(TcpFlags::FIN))),
+    field!("tcp.window", tcp, |segment| Value::Integer(segment.window as u64)),
+    field!("tcp.checksum", tcp, |segment| Value::Integer(segment.checksum as u64)),
+    field!("tcp.checksum_status", tcp, |segment| checksum_status_value(segment.checksum_status)),
+    field!("tcp.urgent_pointer", tcp, |segment| Value::Integer(segment.urgent_pointer as u64)),
+    field!("udp", udp, |_datagram| Value::Bool(true)),
+    field!("udp.source_port", udp, |datagram| Value::Integer(datagram.source_port as u64)),
+    field!("udp.dest_port", udp, |datagram| Value::Integer(datagram.dest_port as u64)),
+    field!("udp.length", udp, |datagram| Value::Integer(datagram.length as u64)),
+    field!("udp.checksum", udp, |datagram| Value::Integer(datagram.checksum as u64)),
+    field!("udp.checksum_status", udp, |datagram| checksum_status_value(datagram.checksum_status)),
+    field!("udp.length_status", udp, |datagram| udp_length_status_value(datagram.length_status)),
+    field!("udp.padding", udp, |datagram| Value::Integer(datagram.padding as u64)),
+    field!("icmp", icmp, |_packet| Value::Bool(true)),
+    field!("icmp.type", icmp, |packet| Value::Integer(packet.message_type as u64)),
+    field!("icmp.code", icmp, |packet| Value::Integer(packet.message_code as u64)),
+    field!("icmp.checksum", icmp, |packet| Value::Integer(packet.checksum as u64)),
+    Field { name: "icmp.identifier", extract: |packet| icmp_id_sequence(packet).map(|(id, _)| Value::Integer(id as u64)) },
+    Field { name: "icmp.sequence_no", extract: |packet| icmp_id_sequence(packet).map(|(_, seq)| Value::Integer(seq as u64)) },
+    field!("icmpv6", icmpv6, |_packet| Value::Bool(true)),
+    field!("icmpv6.type", icmpv6, |packet| Value::Integer(packet.message_type as u64)),
+    field!("icmpv6.code", icmpv6, |packet| Value::Integer(packet.message_code as u64)),
+    field!("icmpv6.checksum", icmpv6, |packet| Value::Integer(packet.checksum as u64)),
+];
+
+pub fn field(name: &str) -> Option<&'static Field> {
+    FIELDS.iter().find(|field| field.name == name)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Literal {
+    Bool(bool),
+    Integer(u64),
+    Ipv4Network(Ipv4Addr, u8),
+    Ipv6Network(Ipv6Addr, u8),
+    Mac(MacAddress),
+    Symbol(String),
+}
+
+fn parse_integer(word: &str) -> Option<u64> {
+    match word.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => word.parse().ok(),
+    }
+}
+
+fn parse_mac(word: &str) -> Option<MacAddress> {
+    let mut mac = [0u8; 6];
+    let mut parts = word.split(':');
+    for byte in mac.iter_mut() {
+        let part = parts.next()?;
+        if part.len() != 2 {
+            return None;
+        }
+        *byte = u8::from_str_radix(part, 16).ok()?;
+    }
+    match parts.next() {
+        Some(_) => None,
+        None => Some(mac),
+    }
+}
+
+impl FromStr for Literal {
+    type Err = FilterError;
+
+    fn from_str(word: &str) -> Result<Self, Self::Err> {
+        if let Some(integer) = parse_integer(word) {
+            return Ok(Self::Integer(integer));
+        }
+        if let Some(mac) = parse_mac(word) {
+            return Ok(Self::Mac(mac));
+        }
+        let (address, prefix) = match word.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (word, None),
+        };
+        let parse_prefix = |max: u8| match prefix {
+            Some(prefix) => match prefix.parse::<u8>() {
+                Ok(prefix) if prefix <= max => Ok(prefix),
+                _ => Err(FilterError::InvalidValue(word.to_string())),
+            },
+            None => Ok(max),
+        };
+        if let Ok(address) = address.parse::<Ipv4Addr>() {
+            return Ok(Self::Ipv4Network(address, parse_prefix(32)?));
+        }
+        if let Ok(address) = address.parse::<Ipv6Addr>() {
+            return Ok(Self::Ipv6Network(address, parse_prefix(128)?));
+        }
+        match word {
+            "true" => Ok(Self::Bool(true)),
+            "false" => Ok(Self::Bool(false)),
+            _ if word.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') => {
+                Ok(Self::Symbol(word.to_ascii_lowercase()))
+            }
+            _ => Err(FilterError::InvalidValue(word.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum SetItem {
+    Single(Literal),
+    Range(u64, u64),
+}
+
+#[derive(Debug)]
+enum Expr {
+    Present(&'static Field),
+    Compare(&'static Field, Comparison, Literal),
+    In(&'static Field, Vec<SetItem>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Comparison(Comparison),
+    And,
+    Or,
+    Not,
+    In,
+    LeftParen,
+    RightParen,
+    LeftBrace,
+    RightBrace,
+    Comma,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Self::Word(word) => word,
+            Self::Comparison(_) => "comparison",
+            Self::And => "&&",
+            Self::Or => "||",
+            Self::Not => "!",
+            Self::In => "in",
+            Self::LeftParen => "(",
+            Self::RightParen => ")",
+            Self::LeftBrace => "{",
+            Self::RightBrace => "}",
+            Self::Comma => ",",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | ':' | '/' | '-')
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, FilterError> {
+    let mut tokens = vec![];
+    let mut chars = input.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let next = chars.peek().map(|&(_, c)| c);
+        let token = match (c, next) {
+            (c, _) if c.is_whitespace() => continue,
+            ('(', _) => Token::LeftParen,
+            (')', _) => Token::RightParen,
+            ('{', _) => Token::LeftBrace,
+            ('}', _) => Token::RightBrace,
+            (',', _) => Token::Comma,
+            ('&', Some('&')) | ('|', Some('|')) | ('=', Some('=')) | ('!', Some('=')) | ('<', Some('='))
+            | ('>', Some('=')) => {
+                chars.next();
+                match c {
+                    '&' => Token::And,
+                    '|' => Token::Or,
+                    '=' => Token::Comparison(Comparison::Equal),
+                    '!' => Token::Comparison(Comparison::NotEqual),
+                    '<' => Token::Comparison(Comparison::LessOrEqual),
+                    _ => Token::Comparison(Comparison::GreaterOrEqual),
+                }
+            }
+            ('!', _) => Token::Not,
+            ('<', _) => Token::Comparison(Comparison::Less),
+            ('>', _) => Token::Comparison(Comparison::Greater),
+            (c, _) if is_word_char(c) => {
+                let mut end = start + c.len_utf8();
+                while let Some(&(index, c)) = chars.peek() {
+                    if !is_word_char(c) {
+                        break;
+                    }
+                    end = index + c.len_utf8();
+                    chars.next();
+                }
+                match &input[start..end] {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    "in" => Token::In,
+                    "eq" => Token::Comparison(Comparison::Equal),
+                    "ne" => Token::Comparison(Comparison::NotEqual),
+                    "lt" => Token::Comparison(Comparison::Less),
+                    "le" => Token::Comparison(Comparison::LessOrEqual),
+                    "gt" => Token::Comparison(Comparison::Greater),
+                    "ge" => Token::Comparison(Comparison::GreaterOrEqual),
+                    word => Token::Word(word.to_string()),
+                }
+            }
+            (c, _) => return Err(FilterError::UnexpectedToken(c.to_string())),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, FilterError> {
+        let token = self.tokens.get(self.position).cloned().ok_or(FilterError::UnexpectedEnd)?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), FilterError> {
+        let token = self.next()?;
+        if token != expected {
+            return Err(FilterError::UnexpectedToken(token.to_string()));
+        }
+        Ok(())
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, FilterError> {
+        let mut expr = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.position += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, FilterError> {
+        let mut expr = self.parse_not()?;
+        while self.peek() == Some(&Token::And) {
+            self.position += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_not()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, FilterError> {
+        if self.peek() == Some(&Token::Not) {
+            self.position += 1;
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, FilterError> {
+        match self.next()? {
+            Token::LeftParen => {
+                let expr = self.parse_or()?;
+                self.expect(Token::RightParen)?;
+                Ok(expr)
+            }
+            Token::Word(name) => {
+                let field = field(&name).ok_or(FilterError::UnknownField(name))?;
+                match self.peek() {
+                    Some(Token::Comparison(comparison)) => {
+                        let comparison = *comparison;
+                        self.position += 1;
+                        let literal = self.parse_literal()?;
+                        Ok(Expr::Compare(field, comparison, literal))
+                    }
+                    Some(Token::In) => {
+                        self.position += 1;
+                        Ok(Expr::In(field, self.parse_set()?))
+                    }
+                    _ => Ok(Expr::Present(field)),
+                }
+            }
+            token => Err(FilterError::UnexpectedToken(token.to_string())),
+        }
+    }
+
+    fn parse_literal(&mut self) -> Result<Literal, FilterError> {
+        match self.next()? {
+            Token::Word(word) => word.parse(),
+            token => Err(FilterError::UnexpectedToken(token.to_string())),
+        }
+    }
+
+    fn parse_set(&mut self) -> Result<Vec<SetItem>, FilterError> {
+        self.expect(Token::LeftBrace)?;
+        let mut items = vec![];
+        loop {
+            match self.next()? {
+                Token::RightBrace if !items.is_empty() => return Ok(items),
+                Token::Comma if !items.is_empty() => continue,
+                Token::Word(word) => match word.split_once("..") {
+                    Some((low, high)) => match (parse_integer(low), parse_integer(high)) {
+                        (Some(low), Some(high)) if low <= high => items.push(SetItem::Range(low, high)),
+                        _ => return Err(FilterError::InvalidValue(word)),
+                    },
+                    None => items.push(SetItem::Single(word.parse()?)),
+                },
+                token => return Err(FilterError::UnexpectedToken(token.to_string())),
+            }
+        }
+    }
+}
+
+fn raw_integer(value: &Value) -> Option<u64> {
+    match value {
+        Value::Integer(raw) | Value::Named(_, raw) => Some(*raw),
+        Value::Bool(flag) => Some(*flag as u64),
+        _ => None,
+    }
+}
+
+fn matches_network(address: Ipv4Addr, network: Ipv4Addr, prefix: u8) -> bool {
+    let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+    u32::from(address) & mask == u32::from(network) & mask
+}
+
+fn matches_ipv6_network(address: Ipv6Addr, network: Ipv6Addr, prefix: u8) -> bool {
+    let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+    u128::from(address) & mask == u128::from(network) & mask
+}
+
+fn equals(value: &Value, literal: &Literal) -> bool {
+    match (value, literal) {
+        (Value::Bool(flag), Literal::Bool(expected)) => flag == expected,
+        (Value::Named(name, _), Literal::Symbol(symbol)) => name == symbol,
+        (Value::Ipv4(address), Literal::Ipv4Network(network, prefix)) => {
+            matches_network(*address, *network, *prefix)
+        }
+        (Value::Ipv6(address), Literal::Ipv6Network(network, prefix)) => {
+            matches_ipv6_network(*address, *network, *prefix)
+        }
+        (Value::Mac(mac), Literal::Mac(expected)) => mac == expected,
+        (value, Literal::Integer(expected)) => raw_integer(value) == Some(*expected),
+        _ => false,
+    }
+}
+
+fn compare(value: &Value, comparison: Comparison, literal: &Literal) -> bool {
+    let ordering = match (value, literal) {
+        (Value::Ipv4(address), Literal::Ipv4Network(network, 32)) => address.cmp(network),
+        (Value::Ipv6(address), Literal::Ipv6Network(network, 128)) => address.cmp(network),
+        (value, Literal::Integer(expected)) => match raw_integer(value) {
+            Some(raw) => raw.cmp(expected),
+            None => return false,
+        },
+        _ => {
+            return match comparison {
+                Comparison::Equal => equals(value, literal),
+                Comparison::NotEqual => !equals(value, literal),
+                _ => false,
+            }
+        }
+    };
+    match comparison {
+        Comparison::Equal => ordering.is_eq(),
+        Comparison::NotEqual => ordering.is_ne(),
+        Comparison::Less => ordering.is_lt(),
+        Comparison::LessOrEqual => ordering.is_le(),
+        Comparison::Greater => ordering.is_gt(),
+        Comparison::GreaterOrEqual => ordering.is_ge(),
+    }
+}
+
+impl Expr {
+    fn evaluate(&self, packet: &Packet) -> bool {
+        match self {
+            Self::Present(field) => match (field.extract)(packet) {
+                Some(Value::Bool(flag)) => flag,
+                Some(_) => true,
+                None => false,
+            },
+            Self::Compare(field, comparison, literal) => match (field.extract)(packet) {
+                Some(value) => compare(&value, *comparison, literal),
+                None => false,
+            },
+            Self::In(field, items) => match (field.extract)(packet) {
+                Some(value) => items.iter().any(|item| match item {
+                    SetItem::Single(literal) => equals(&value, literal),
+                    SetItem::Range(low, high) => {
+                        raw_integer(&value).is_some_and(|raw| *low <= raw && raw <= *high)
+                    }
+                }),
+                None => false,
+            },
+            Self::Not(expr) => !expr.evaluate(packet),
+            Self::And(left, right) => left.evaluate(packet) && right.evaluate(packet),
+            Self::Or(left, right) => left.evaluate(packet) || right.evaluate(packet),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DisplayFilter {
+    expr: Expr,
+}
+
+impl DisplayFilter {
+    pub fn matches(&self, packet: &Packet) -> bool {
+        self.expr.evaluate(packet)
+    }
+}
+
+impl FromStr for DisplayFilter {
+    type Err = FilterError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { tokens: tokenize(input)?, position: 0 };
+        let expr = parser.parse_or()?;
+        match parser.peek() {
+            Some(token) => Err(FilterError::UnexpectedToken(token.to_string())),
+            None => Ok(Self { expr }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{dissect, LinkType};
+
+    // A TCP SYN from 10.0.0.1:40000 to 10.0.0.2:80 in an Ethernet frame.
+    const FRAME: [u8; 54] = [
+        0x02, 0, 0, 0, 0, 0x02, 0x02, 0, 0, 0, 0, 0x01, 0x08, 0x00,
+        0x45, 0, 0, 0x28, 0, 0x01, 0x40, 0, 0x40, 0x06, 0x26, 0xcd, 10, 0, 0, 1, 10, 0, 0, 2,
+        0x9c, 0x40, 0, 0x50, 0, 0, 0x03, 0xe8, 0, 0, 0x07, 0xd0, 0x50, 0x02, 0xff, 0xff, 0, 0, 0, 0,
+    ];
+
+    // Renders the parsed expression with explicit grouping.
+    fn describe(expr: &Expr) -> String {
+        match expr {
+            Expr::Present(field) => field.name.to_string(),
+            Expr::Compare(field, comparison, literal) => format!("({} {:?} {:?})", field.name, comparison, literal),
+            Expr::In(field, items) => format!("({} in {:?})", field.name, items),
+            Expr::Not(expr) => format!("!{}", describe(expr)),
+            Expr::And(left, right) => format!("({} && {})", describe(left), describe(right)),
+            Expr::Or(left, right) => format!("({} || {})", describe(left), describe(right)),
+        }
+    }
+
+    fn parse(input: &str) -> String {
+        match input.parse::<DisplayFilter>() {
+            Ok(filter) => describe(&filter.expr),
+            Err(e) => panic!("failed to parse {:?}: {}", input, e),
+        }
+    }
+
+    fn error(input: &str) -> FilterError {
+        match input.parse::<DisplayFilter>() {
+            Ok(filter) => panic!("parsed {:?} as {}", input, describe(&filter.expr)),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(parse("tcp || udp && icmp"), "(tcp || (udp && icmp))");
+        assert_eq!(parse("tcp && udp || icmp"), "((tcp && udp) || icmp)");
+        assert_eq!(parse("tcp or udp and icmp or arp"), "((tcp || (udp && icmp)) || arp)");
+        assert_eq!(parse("(tcp || udp) && icmp"), "((tcp || udp) && icmp)");
+    }
+
+    #[test]
+    fn operators_are_left_associative() {
+        assert_eq!(parse("tcp && udp && icmp"), "((tcp && udp) && icmp)");
+        assert_eq!(parse("tcp || udp || icmp"), "((tcp || udp) || icmp)");
+    }
+
+    #[test]
+    fn negation_binds_tightest() {
+        assert_eq!(parse("!tcp && udp"), "(!tcp && udp)");
+        assert_eq!(parse("not tcp or udp"), "(!tcp || udp)");
+        assert_eq!(parse("!(tcp || udp)"), "!(tcp || udp)");
+        assert_eq!(parse("!!tcp"), "!!tcp");
+        assert_eq!(parse("!tcp.dest_port == 80"), "!(tcp.dest_port Equal Integer(80))");
+    }
+
+    #[test]
+    fn comparisons_and_literals() {
+        assert_eq!(parse("tcp.dest_port eq 0x50"), "(tcp.dest_port Equal Integer(80))");
+        assert_eq!(parse("tcp.window<=100"), "(tcp.window LessOrEqual Integer(100))");
+        assert_eq!(parse("tcp.window != 0"), "(tcp.window NotEqual Integer(0))");
+        assert_eq!(parse("ipv4.protocol == TCP"), "(ipv4.protocol Equal Symbol(\"tcp\"))");
+        assert_eq!(parse("tcp.flag_syn == true"), "(tcp.flag_syn Equal Bool(true))");
+        assert_eq!(
+            parse("ipv4.source_addr == 10.0.0.0/8"),
+            "(ipv4.source_addr Equal Ipv4Network(10.0.0.0, 8))"
+        );
+        assert_eq!(parse("ipv6.dest_addr == ff02::1"), "(ipv6.dest_addr Equal Ipv6Network(ff02::1, 128))");
+        assert_eq!(
+            parse("eth.source_mac == 02:00:00:00:00:01"),
+            "(eth.source_mac Equal Mac([2, 0, 0, 0, 0, 1]))"
+        );
+        assert_eq!(
+            parse("tcp.dest_port in {22, 80..90}"),
+            "(tcp.dest_port in [Single(Integer(22)), Range(80, 90)])"
+        );
+    }
+
+    #[test]
+    fn malformed_expressions() {
+        assert_eq!(error(""), FilterError::UnexpectedEnd);
+        assert_eq!(error("tcp &&"), FilterError::UnexpectedEnd);
+        assert_eq!(error("(tcp"), FilterError::UnexpectedEnd);
+        assert_eq!(error("tcp.dest_port =="), FilterError::UnexpectedEnd);
+        assert_eq!(error("tcp)"), FilterError::UnexpectedToken(")".to_string()));
+        assert_eq!(error("tcp udp"), FilterError::UnexpectedToken("udp".to_string()));
+        assert_eq!(error("&& tcp"), FilterError::UnexpectedToken("&&".to_string()));
+        assert_eq!(error("tcp.dest_port == == 80"), FilterError::UnexpectedToken("comparison".to_string()));
+        assert_eq!(error("tcp & udp"), FilterError::UnexpectedToken("&".to_string()));
+        assert_eq!(error("tcp.dest_port in {}"), FilterError::UnexpectedToken("}".to_string()));
+        assert_eq!(error("tcp.dest_port in 80"), FilterError::UnexpectedToken("80".to_string()));
+        assert_eq!(error("tcp.port == 80"), FilterError::UnknownField("tcp.port".to_string()));
+        assert_eq!(error("tcp.dest_port in {90..80}"), FilterError::InvalidValue("90..80".to_string()));
+        assert_eq!(error("ipv4.source_addr == 10.0.0.0/33"), FilterError::InvalidValue("10.0.0.0/33".to_string()));
+        assert_eq!(error("tcp.dest_port == 8.0"), FilterError::InvalidValue("8.0".to_string()));
+    }
+
+    #[test]
+    fn every_field_parses() {
+        for field in FIELDS {
+            assert_eq!(FIELDS.iter().filter(|other| other.name == field.name).count(), 1, "{}", field.name);
+            assert_eq!(parse(field.name), field.name);
+            parse(&format!("{} == 1", field.name));
+            parse(&format!("!{} || {} in {{1, 2..3}}", field.name, field.name));
+        }
+    }
+
+    #[test]
+    fn evaluates_fields_of_a_tcp_packet() {
+        let packet = dissect(LinkType::Ethernet, &FRAME);
+        let matches = |input: &str| input.parse::<DisplayFilter>().unwrap().matches(&packet);
+
+        for input in [
+            "eth && ipv4 && tcp",
+            "eth.source_mac == 02:00:00:00:00:01",
+            "ipv4.protocol == tcp",
+            "ipv4.source_addr == 10.0.0.1",
+            "ipv4.dest_addr == 10.0.0.0/24",
+            "ipv4.source_addr < 10.0.0.2",
+            "ipv4.ttl == 64",
+            "ipv4.checksum_status == good",
+            "tcp.source_port == 40000 && tcp.dest_port == 80",
+            "tcp.dest_port in {22, 80..90}",
+            "tcp.sequence_no == 1000",
+            "tcp.ack_no >= 2000",
+            "tcp.flag_syn",
+            "!tcp.flag_ack",
+            "tcp.flags == 0x02",
+            "tcp.window == 65535",
+            "tcp.header_length == 5",
+        ] {
+            assert!(matches(input), "{}", input);
+        }
+        for input in [
+            "udp",
+            "ipv6",
+            "arp",
+            "tcp.dest_port == 443",
+            "ipv4.dest_addr == 10.0.1.0/24",
+            "tcp.flag_syn && tcp.flag_ack",
+            "udp.dest_port != 80",
+            "ipv4.protocol == udp",
+        ] {
+            assert!(!matches(input), "{}", input);
+        }
+        for field in FIELDS {
+            let present = (field.extract)(&packet).is_some();
+            let expected = ["eth", "ipv4", "tcp"].iter().any(|layer| field.name.split('.').next() == Some(layer));
+            assert_eq!(present, expected, "{}", field.name);
+        }
+    }
+}
@@ -0,0 +1,527 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::time::Duration;
+use crate::packet::Packet;
+use crate::tcp::{TcpFlags, TcpOption, TcpSegment};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Endpoint {
+    pub addr: IpAddr,
+    pub port: u16,
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.addr {
+            IpAddr::V4(addr) => write!(f, "{}:{}", addr, self.port),
+            IpAddr::V6(addr) => write!(f, "[{}]:{}", addr, self.port),
+        }
+    }
+}
+
+// The same key is used for both directions of a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FlowKey {
+    pub low: Endpoint,
+    pub high: Endpoint,
+}
+
+impl FlowKey {
+    pub fn new(a: Endpoint, b: Endpoint) -> Self {
+        if a <= b {
+            FlowKey { low: a, high: b }
+        } else {
+            FlowKey { low: b, high: a }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    ClientToServer,
+    ServerToClient,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcpState {
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait,
+    TimeWait,
+    Reset,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    Fin,
+    Reset,
+    Timeout,
+    EndOfCapture,
+    // A new SYN reused the 4-tuple before the connection was closed.
+    Reused,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlowConfig {
+    pub idle_timeout: Duration,
+    // How long a closed connection is kept around to absorb retransmissions.
+    pub close_timeout: Duration,
+}
+
+impl Default for FlowConfig {
+    fn default() -> Self {
+        FlowConfig {
+            idle_timeout: Duration::from_secs(300),
+            close_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PeerStats {
+    pub segments: u64,
+    pub bytes: u64,
+    pub initial_sequence_no: Option<u32>,
+    pub mss: Option<u16>,
+    pub window_scale: Option<u8>,
+    pub sack_permitted: bool,
+    // Sequence number just past the FIN, once one has been sent.
+    pub fin_sequence_no: Option<u32>,
+    pub fin_acked: bool,
+}
+
+impl PeerStats {
+    fn record_syn(&mut self, segment: &TcpSegment) {
+        self.initial_sequence_no = Some(segment.sequence_no);
+        for option in segment.options.iter().flatten() {
+            match option {
+                TcpOption::MaximumSegmentSize(mss) => self.mss = Some(*mss),
+                TcpOption::WindowScale(shift) => self.window_scale = Some(*shift),
+                TcpOption::SackPermitted => self.sack_permitted = true,
+                _ => {}
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Connection {
+    pub client: Endpoint,
+    pub server: Endpoint,
+    pub state: TcpState,
+    pub first_seen: Duration,
+    pub last_seen: Duration,
+    pub syn_time: Option<Duration>,
+    pub syn_ack_time: Option<Duration>,
+    pub established_time: Option<Duration>,
+    pub closed_time: Option<Duration>,
+    pub client_side: PeerStats,
+    pub server_side: PeerStats,
+    pub close_reason: Option<CloseReason>,
+}
+
+impl Connection {
+    fn new(client: Endpoint, server: Endpoint, state: TcpState, now: Duration) -> Self {
+        Connection {
+            client,
+            server,
+            state,
+            first_seen: now,
+            last_seen: now,
+            syn_time: None,
+            syn_ack_time: None,
+            established_time: None,
+            closed_time: None,
+            client_side: PeerStats::default(),
+            server_side: PeerStats::default(),
+            close_reason: None,
+        }
+    }
+
+    pub fn key(&self) -> FlowKey {
+        FlowKey::new(self.client, self.server)
+    }
+
+    pub fn direction(&self, source: Endpoint) -> Direction {
+        if source == self.client {
+            Direction::ClientToServer
+        } else {
+            Direction::ServerToClient
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.last_seen.saturating_sub(self.first_seen)
+    }
+
+    // Time from the client's SYN to the ACK completing the three-way handshake.
+    pub fn handshake_duration(&self) -> Option<Duration> {
+        Some(self.established_time?.saturating_sub(self.syn_time?))
+    }
+
+    // Window scaling is only in effect when both sides offered it.
+    pub fn window_scaling(&self) -> Option<(u8, u8)> {
+        Some((self.client_side.window_scale?, self.server_side.window_scale?))
+    }
+
+    pub fn sack_permitted(&self) -> bool {
+        self.client_side.sack_permitted && self.server_side.sack_permitted
+    }
+
+    fn is_closed(&self) -> bool {
+        matches!(self.state, TcpState::TimeWait | TcpState::Reset)
+    }
+
+    // Once past the handshake, a new SYN can only start another connection.
+    fn is_past_handshake(&self) -> bool {
+        !matches!(self.state, TcpState::SynSent | TcpState::SynReceived)
+    }
+
+    fn update(&mut self, now: Duration, direction: Direction, segment: &TcpSegment, length: usize) {
+        let flags = segment.flags;
+        self.last_seen = now;
+        let (sender, receiver) = match direction {
+            Direction::ClientToServer => (&mut self.client_side, &mut self.server_side),
+            Direction::ServerToClient => (&mut self.server_side, &mut self.client_side),
+        };
+        sender.segments += 1;
+        sender.bytes += length as u64;
+        if flags.contains(TcpFlags::SYN) {
+            sender.record_syn(segment);
+        }
+        if flags.contains(TcpFlags::FIN) {
+            sender.fin_sequence_no = Some(segment.sequence_no.wrapping_add(segment.sequence_length(length)));
+        }
+        if flags.contains(TcpFlags::ACK) && receiver.fin_sequence_no == Some(segment.ack_no) {
+            receiver.fin_acked = true;
+        }
+
+        if self.is_closed() {
+            return;
+        }
+        if flags.contains(TcpFlags::RST) {
+            self.state = TcpState::Reset;
+            self.close_reason = Some(CloseReason::Reset);
+            self.closed_time = Some(now);
+            return;
+        }
+        match (self.state, direction) {
+            (TcpState::SynSent, Direction::ServerToClient)
+                if flags.contains(TcpFlags::SYN | TcpFlags::ACK) =>
+            {
+                self.state = TcpState::SynReceived;
+                self.syn_ack_time = Some(now);
+            }
+            (TcpState::SynReceived, Direction::ClientToServer)
+                if flags.contains(TcpFlags::ACK) && !flags.contains(TcpFlags::SYN) =>
+            {
+                self.state = TcpState::Established;
+                self.established_time = Some(now);
+            }
+            _ => {}
+        }
+        if self.client_side.fin_acked && self.server_side.fin_acked {
+            self.state = TcpState::TimeWait;
+            self.close_reason = Some(CloseReason::Fin);
+            self.closed_time = Some(now);
+        } else if self.client_side.fin_sequence_no.is_some() || self.server_side.fin_sequence_no.is_some() {
+            self.state = TcpState::FinWait;
+        }
+    }
+
+    fn finish(mut self, reason: CloseReason) -> Connection {
+        if self.close_reason.is_none() {
+            // Both FINs were seen but the last ACK never was.
+            let both_fins = self.client_side.fin_sequence_no.is_some() && self.server_side.fin_sequence_no.is_some();
+            self.close_reason = Some(if both_fins { CloseReason::Fin } else { reason });
+        }
+        self
+    }
+}
+
+impl fmt::Display for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TCP {} -> {} {:?}", self.client, self.server, self.state)?;
+        if let Some(reason) = self.close_reason {
+            write!(f, ", closed by {:?}", reason)?;
+        }
+        write!(
+            f,
+            " after {:.3}s: {} segments/{} bytes sent, {} segments/{} bytes received",
+            self.duration().as_secs_f64(),
+            self.client_side.segments,
+            self.client_side.bytes,
+            self.server_side.segments,
+            self.server_side.bytes
+        )?;
+        if let Some(handshake) = self.handshake_duration() {
+            write!(f, ", handshake {:.3}ms", handshake.as_secs_f64() * 1000.0)?;
+        }
+        if let (Some(client), Some(server)) = (self.client_side.mss, self.server_side.mss) {
+            write!(f, ", MSS {}/{}", client, server)?;
+        }
+        if let Some((client, server)) = self.window_scaling() {
+            write!(f, ", window scale {}/{}", client, server)?;
+        }
+        if self.sack_permitted() {
+            write!(f, ", SACK")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct FlowTracker {
+    config: FlowConfig,
+    connections: HashMap<FlowKey, Connection>,
+}
+
+impl FlowTracker {
+    pub fn new(config: FlowConfig) -> Self {
+        FlowTracker { config, connections: HashMap::new() }
+    }
+
+    pub fn active(&self) -> usize {
+        self.connections.len()
+    }
+
+    pub fn get(&self, key: &FlowKey) -> Option<&Connection> {
+        self.connections.get(key)
+    }
+
+    pub fn connections(&self) -> impl Iterator<Item = &Connection> {
+        self.connections.values()
+    }
+
+    fn drain<F: Fn(&Connection) -> Option<CloseReason>>(&mut self, select: F) -> Vec<Connection> {
+        let keys: Vec<(FlowKey, CloseReason)> = self
+            .connections
+            .iter()
+            .filter_map(|(key, connection)| select(connection).map(|reason| (*key, reason)))
+            .collect();
+        let mut finished: Vec<Connection> = keys
+            .into_iter()
+            .filter_map(|(key, reason)| self.connections.remove(&key).map(|connection| connection.finish(reason)))
+            .collect();
+        finished.sort_by_key(|connection| connection.first_seen);
+        finished
+    }
+
+    pub fn expire(&mut self, now: Duration) -> Vec<Connection> {
+        let config = self.config;
+        self.drain(|connection| match connection.closed_time {
+            Some(closed) if connection.is_closed() => {
+                (now.saturating_sub(closed) > config.close_timeout).then_some(CloseReason::Timeout)
+            }
+            _ => (now.saturating_sub(connection.last_seen) > config.idle_timeout).then_some(CloseReason::Timeout),
+        })
+    }
+
+    pub fn flush(&mut self) -> Vec<Connection> {
+        self.drain(|_| Some(CloseReason::EndOfCapture))
+    }
+
+    // Feeds one packet to the tracker and returns the connections that ended,
+    // including the ones that timed out before it.
+    pub fn process(&mut self, now: Duration, packet: &Packet) -> Vec<Connection> {
+        let mut finished = self.expire(now);
+        let (segment, (source_addr, dest_addr)) = match (packet.tcp(), packet.ip_addresses()) {
+            (Some(segment), Some(addresses)) => (segment, addresses),
+            _ => return finished,
+        };
+        let source = Endpoint { addr: source_addr, port: segment.source_port };
+        let dest = Endpoint { addr: dest_addr, port: segment.dest_port };
+        let key = FlowKey::new(source, dest);
+        let flags = segment.flags;
+        let new_syn = flags.contains(TcpFlags::SYN) && !flags.contains(TcpFlags::ACK);
+
+        // A fresh SYN on a closed or established connection means the 4-tuple is
+        // being reused, while one during the handshake is a retransmission.
+        if new_syn && self.connections.get(&key).is_some_and(|connection| connection.is_past_handshake()) {
+            if let Some(connection) = self.connections.remove(&key) {
+                finished.push(connection.finish(CloseReason::Reused));
+            }
+        }
+        let connection = match self.connections.entry(key) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let connection = if new_syn {
+                    let mut connection = Connection::new(source, dest, TcpState::SynSent, now);
+                    connection.syn_time = Some(now);
+                    connection
+                } else if flags.contains(TcpFlags::SYN) {
+                    // The client's SYN was missed and this is the server's SYN-ACK.
+                    Connection::new(dest, source, TcpState::SynSent, now)
+                } else if flags.contains(TcpFlags::RST) {
+                    return finished;
+                } else {
+                    // Picked up mid-stream: guess that the side using the higher port is the client.
+                    let (client, server) = if source.port >= dest.port { (source, dest) } else { (dest, source) };
+                    Connection::new(client, server, TcpState::Established, now)
+                };
+                entry.insert(connection)
+            }
+        };
+        let direction = connection.direction(source);
+        connection.update(now, direction, segment, packet.payload.len());
+        finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{dissect, LinkType};
+    use crate::testing::{tcp, tcp_with, CLIENT, SERVER};
+
+    const ACK: TcpFlags = TcpFlags::ACK;
+    // MSS 1460, window scale 7 and SACK permitted.
+    const SYN_OPTIONS: [u8; 12] = [2, 4, 0x05, 0xb4, 1, 3, 3, 7, 4, 2, 1, 1];
+
+    fn endpoint((addr, port): ([u8; 4], u16)) -> Endpoint {
+        Endpoint { addr: IpAddr::from(addr), port }
+    }
+
+    fn key() -> FlowKey {
+        FlowKey::new(endpoint(CLIENT), endpoint(SERVER))
+    }
+
+    fn send(tracker: &mut FlowTracker, millis: u64, bytes: &[u8]) -> Vec<Connection> {
+        tracker.process(Duration::from_millis(millis), &dissect(LinkType::IPv4, bytes))
+    }
+
+    fn state(tracker: &FlowTracker) -> Option<TcpState> {
+        tracker.get(&key()).map(|connection| connection.state)
+    }
+
+    // Handshake with options, starting at `start` ms with client ISN `isn`.
+    fn handshake(tracker: &mut FlowTracker, start: u64, isn: u32) {
+        send(tracker, start, &tcp_with(true, isn, 0, TcpFlags::SYN, 65535, &SYN_OPTIONS, b""));
+        send(tracker, start + 10, &tcp_with(false, 5000, isn + 1, TcpFlags::SYN | ACK, 65535, &SYN_OPTIONS, b""));
+        send(tracker, start + 20, &tcp(true, isn + 1, 5001, ACK, b""));
+    }
+
+    #[test]
+    fn follows_the_handshake_and_close() {
+        let mut tracker = FlowTracker::new(FlowConfig::default());
+        send(&mut tracker, 0, &tcp_with(true, 0, 0, TcpFlags::SYN, 65535, &SYN_OPTIONS, b""));
+        assert_eq!(state(&tracker), Some(TcpState::SynSent));
+        send(&mut tracker, 10, &tcp_with(false, 5000, 1, TcpFlags::SYN | ACK, 65535, &SYN_OPTIONS, b""));
+        assert_eq!(state(&tracker), Some(TcpState::SynReceived));
+        send(&mut tracker, 20, &tcp(true, 1, 5001, ACK, b""));
+        assert_eq!(state(&tracker), Some(TcpState::Established));
+
+        send(&mut tracker, 30, &tcp(true, 1, 5001, ACK, b"hello"));
+        send(&mut tracker, 40, &tcp(true, 6, 5001, TcpFlags::FIN | ACK, b""));
+        assert_eq!(state(&tracker), Some(TcpState::FinWait));
+        send(&mut tracker, 50, &tcp(false, 5001, 7, TcpFlags::FIN | ACK, b""));
+        assert!(send(&mut tracker, 60, &tcp(true, 7, 5002, ACK, b"")).is_empty());
+        assert_eq!(state(&tracker), Some(TcpState::TimeWait));
+
+        let connections = tracker.flush();
+        assert_eq!(connections.len(), 1);
+        let connection = &connections[0];
+        assert_eq!(connection.client, endpoint(CLIENT));
+        assert_eq!(connection.close_reason, Some(CloseReason::Fin));
+        assert_eq!(connection.closed_time, Some(Duration::from_millis(60)));
+        assert_eq!(connection.handshake_duration(), Some(Duration::from_millis(20)));
+        assert_eq!(connection.client_side.segments, 5);
+        assert_eq!(connection.client_side.bytes, 5);
+        assert_eq!(connection.client_side.initial_sequence_no, Some(0));
+        assert_eq!(connection.server_side.mss, Some(1460));
+        assert_eq!(connection.window_scaling(), Some((7, 7)));
+        assert!(connection.sack_permitted());
+    }
+
+    #[test]
+    fn close_reasons() {
+        let mut tracker = FlowTracker::new(FlowConfig::default());
+        handshake(&mut tracker, 0, 0);
+        send(&mut tracker, 30, &tcp(false, 5001, 1, TcpFlags::RST, b""));
+        assert_eq!(state(&tracker), Some(TcpState::Reset));
+        let connections = tracker.flush();
+        assert_eq!(connections[0].close_reason, Some(CloseReason::Reset));
+
+        // Both FINs without the last ACK still count as a FIN close.
+        handshake(&mut tracker, 0, 0);
+        send(&mut tracker, 30, &tcp(true, 1, 5001, TcpFlags::FIN | ACK, b""));
+        send(&mut tracker, 40, &tcp(false, 5001, 2, TcpFlags::FIN | ACK, b""));
+        assert_eq!(state(&tracker), Some(TcpState::FinWait));
+        assert_eq!(tracker.flush()[0].close_reason, Some(CloseReason::Fin));
+
+        handshake(&mut tracker, 0, 0);
+        send(&mut tracker, 30, &tcp(true, 1, 5001, TcpFlags::FIN | ACK, b""));
+        assert_eq!(tracker.flush()[0].close_reason, Some(CloseReason::EndOfCapture));
+    }
+
+    #[test]
+    fn connections_picked_up_late() {
+        let mut tracker = FlowTracker::new(FlowConfig::default());
+        // Mid-stream, the side with the higher port is taken to be the client.
+        send(&mut tracker, 0, &tcp(false, 5001, 1, ACK, b"data"));
+        let connection = tracker.get(&key()).unwrap();
+        assert_eq!(connection.client, endpoint(CLIENT));
+        assert_eq!(connection.state, TcpState::Established);
+        assert_eq!(connection.server_side.bytes, 4);
+        tracker.flush();
+
+        // Starting from the SYN-ACK, the client is its destination.
+        send(&mut tracker, 0, &tcp(false, 5000, 1, TcpFlags::SYN | ACK, b""));
+        assert_eq!(tracker.get(&key()).unwrap().client, endpoint(CLIENT));
+        assert_eq!(state(&tracker), Some(TcpState::SynReceived));
+        tracker.flush();
+
+        // A reset for a connection that was never seen is ignored.
+        send(&mut tracker, 0, &tcp(true, 1, 0, TcpFlags::RST, b""));
+        assert_eq!(tracker.active(), 0);
+    }
+
+    #[test]
+    fn idle_and_closed_connections_time_out() {
+        let mut tracker = FlowTracker::new(FlowConfig::default());
+        handshake(&mut tracker, 0, 0);
+        assert!(tracker.expire(Duration::from_millis(300_020)).is_empty());
+        let expired = tracker.expire(Duration::from_millis(300_021));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].close_reason, Some(CloseReason::Timeout));
+
+        // Closed connections only linger for the close timeout, and keep their reason.
+        handshake(&mut tracker, 0, 0);
+        send(&mut tracker, 30, &tcp(true, 1, 5001, TcpFlags::RST, b""));
+        assert!(tracker.expire(Duration::from_millis(10_030)).is_empty());
+        // Expiry happens as packets of other connections go by too.
+        let expired = send(&mut tracker, 10_031, &tcp(true, 0, 0, TcpFlags::SYN, b""));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].close_reason, Some(CloseReason::Reset));
+        assert_eq!(tracker.active(), 1);
+    }
+
+    #[test]
+    fn new_syn_reuses_the_four_tuple() {
+        let mut tracker = FlowTracker::new(FlowConfig::default());
+        // A retransmitted SYN during the handshake belongs to the same connection.
+        send(&mut tracker, 0, &tcp(true, 0, 0, TcpFlags::SYN, b""));
+        handshake(&mut tracker, 1000, 0);
+        assert_eq!(tracker.get(&key()).unwrap().syn_time, Some(Duration::ZERO));
+
+        // The connection was never closed, but its handshake is done.
+        let finished = send(&mut tracker, 2000, &tcp(true, 90000, 0, TcpFlags::SYN, b""));
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].close_reason, Some(CloseReason::Reused));
+        assert_eq!(finished[0].client_side.initial_sequence_no, Some(0));
+        assert_eq!(finished[0].client_side.mss, Some(1460));
+        let connection = tracker.get(&key()).unwrap();
+        assert_eq!(connection.state, TcpState::SynSent);
+        assert_eq!(connection.client_side.initial_sequence_no, Some(90000));
+        assert_eq!(connection.client_side.mss, None);
+
+        // A reset connection is replaced too, keeping its own close reason.
+        send(&mut tracker, 2010, &tcp(false, 0, 90001, TcpFlags::RST | ACK, b""));
+        let finished = send(&mut tracker, 3000, &tcp(true, 100000, 0, TcpFlags::SYN, b""));
+        assert_eq!(finished[0].close_reason, Some(CloseReason::Reset));
+        assert_eq!(tracker.active(), 1);
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::Duration;
+use mohole::flow::{Endpoint, FlowKey};
+use mohole::stream::{StreamConfig, StreamEvent, StreamReassembler};
+use mohole::tcp::TcpFlags;
+use mohole::Packet;
+
+const SEPARATOR: &str = "===================================================================";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamSelector {
+    // Connections are numbered from 0 in the order they first appear.
+    Index(usize),
+    Endpoints(Endpoint, Endpoint),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FollowFormat {
+    Text,
+    Hex,
+    Raw,
+}
+
+#[derive(Debug, Clone)]
+pub struct FollowOptions {
+    pub selector: StreamSelector,
+    pub format: FollowFormat,
+    pub output: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Client,
+    Server,
+}
+
+pub struct Follower {
+    options: FollowOptions,
+    indexes: HashMap<FlowKey, usize>,
+    selected: Option<FlowKey>,
+    client: Option<Endpoint>,
+    reassembler: StreamReassembler,
+    chunks: Vec<(Side, Chunk)>,
+}
+
+enum Chunk {
+    Data { offset: u64, data: Vec<u8> },
+    Gap(u64),
+}
+
+impl Follower {
+    pub fn new(options: FollowOptions) -> Self {
+        let selected = match options.selector {
+            StreamSelector::Endpoints(a, b) => Some(FlowKey::new(a, b)),
+            StreamSelector::Index(_) => None,
+        };
+        Follower {
+            options,
+            indexes: HashMap::new(),
+            selected,
+            client: None,
+            reassembler: StreamReassembler::new(StreamConfig::default()),
+            chunks: vec![],
+        }
+    }
+
+    pub fn process(&mut self, now: Duration, packet: &Packet) {
+        let (segment, (source_addr, dest_addr)) = match (packet.tcp(), packet.ip_addresses()) {
+            (Some(segment), Some(addresses)) => (segment, addresses),
+            _ => return,
+        };
+        let source = Endpoint { addr: source_addr, port: segment.source_port };
+        let dest = Endpoint { addr: dest_addr, port: segment.dest_port };
+        let key = FlowKey::new(source, dest);
+        let next_index = self.indexes.len();
+        let index = *self.indexes.entry(key).or_insert(next_index);
+        if self.selected.is_none() && self.options.selector == StreamSelector::Index(index) {
+            self.selected = Some(key);
+        }
+        if self.selected != Some(key) {
+            return;
+        }
+        if self.client.is_none() {
+            let flags = segment.flags;
+            let server_first = flags.contains(TcpFlags::SYN | TcpFlags::ACK);
+            self.client = Some(if server_first { dest } else { source });
+        }
+        let client = self.client;
+        let chunks = &mut self.chunks;
+        self.reassembler.process_with(now, packet, &mut |event| record(chunks, client, event));
+    }
+
+    pub fn finish(mut self) -> Result<(), String> {
+        let client = self.client;
+        let chunks = &mut self.chunks;
+        self.reassembler.flush_with(&mut |event| record(chunks, client, event));
+        let (key, client) = match (self.selected, self.client) {
+            (Some(key), Some(client)) => (key, client),
+            _ => return Err("no matching TCP connection in the capture".to_string()),
+        };
+        let server = if key.low == client { key.high } else { key.low };
+        match self.options.format {
+            FollowFormat::Text => print_text(client, server, &self.chunks),
+            FollowFormat::Hex => print_hex(client, server, &self.chunks),
+            FollowFormat::Raw => write_raw(&self.options.output, &self.chunks)?,
+        }
+        Ok(())
+    }
+}
+
+fn record(chunks: &mut Vec<(Side, Chunk)>, client: Option<Endpoint>, event: StreamEvent) {
+    let side = |source: Endpoint| if Some(source) == client { Side::Client } else { Side::Server };
+    match event {
+        StreamEvent::Data { stream, offset, data } => {
+            let side = side(stream.source);
+            // Merge consecutive data from the same side into one chunk.
+            if let Some((last_side, Chunk::Data { data: last, .. })) = chunks.last_mut() {
+                if *last_side == side {
+                    last.extend_from_slice(&data);
+                    return;
+                }
+            }
+            chunks.push((side, Chunk::Data { offset, data }));
+        }
+        StreamEvent::Gap { stream, length, .. } => chunks.push((side(stream.source), Chunk::Gap(length))),
+        StreamEvent::End { .. } => {}
+    }
+}
+
+fn print_header(format: &str, client: Endpoint, server: Endpoint) {
+    println!("{}", SEPARATOR);
+    println!("Follow: tcp,{}", format);
+    println!("Node 0: {}", client);
+    println!("Node 1: {}", server);
+}
+
+// Server data is indented so the two sides can be told apart.
+fn indent(side: Side) -> &'static str {
+    match side {
+        Side::Client => "",
+        Side::Server => "\t",
+    }
+}
+
+fn print_text(client: Endpoint, server: Endpoint, chunks: &[(Side, Chunk)]) {
+    print_header("text", client, server);
+    for (side, chunk) in chunks {
+        match chunk {
+            Chunk::Data { data, .. } => {
+                let text: String = data
+                    .iter()
+                    .map(|&byte| match byte {
+                        b'\n' | b'\r' | b'\t' | 0x20..=0x7E => byte as char,
+                        _ => '.',
+                    })
+                    .collect();
+                for line in text.lines() {
+                    println!("{}{}", indent(*side), line);
+                }
+            }
+            Chunk::Gap(length) => println!("{}[{} bytes missing in capture]", indent(*side), length),
+        }
+    }
+    println!("{}", SEPARATOR);
+}
+
+fn print_hex(client: Endpoint, server: Endpoint, chunks: &[(Side, Chunk)]) {
+    print_header("hex", client, server);
+    for (side, chunk) in chunks {
+        match chunk {
+            Chunk::Data { offset, data } => {
+                for (row, bytes) in data.chunks(16).enumerate() {
+                    let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+                    let ascii: String = bytes
+                        .iter()
+                        .map(|&byte| if (0x20..=0x7E).contains(&byte) { byte as char } else { '.' })
+                        .collect();
+                    println!("{}{:08X}  {:<47}  {}", indent(*side), offset + row as u64 * 16, hex.join(" "), ascii);
+                }
+            }
+            Chunk::Gap(length) => println!("{}[{} bytes missing in capture]", indent(*side), length),
+        }
+    }
+    println!("{}", SEPARATOR);
+}
+
+fn write_raw(prefix: &str, chunks: &[(Side, Chunk)]) -> Result<(), String> {
+    for (side, name) in [(Side::Client, "client"), (Side::Server, "server")] {
+        let path = format!("{}.{}.bin", prefix, name);
+        let file = File::create(&path).map_err(|e| format!("cannot create {}: {}", path, e))?;
+        let mut writer = BufWriter::new(file);
+        let mut written = 0;
+        let mut missing = 0;
+        for (_, chunk) in chunks.iter().filter(|(chunk_side, _)| *chunk_side == side) {
+            match chunk {
+                Chunk::Data { data, .. } => {
+                    writer.write_all(data).map_err(|e| format!("cannot write {}: {}", path, e))?;
+                    written += data.len();
+                }
+                Chunk::Gap(length) => missing += length,
+            }
+        }
+        writer.flush().map_err(|e| format!("cannot write {}: {}", path, e))?;
+        println!("Wrote {} bytes of {} data to {} ({} bytes missing in capture)", written, name, path, missing);
+    }
+    Ok(())
+}
@@ -1,4 +1,10 @@
-use std::array::TryFromSliceError;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use crate::bytes::{ensure_length, read_array, read_u16, read_u32, read_u8, skip};
+use crate::checksum::{checksum_status, ones_complement_sum, ChecksumStatus};
+use crate::error::ParseError;
+use crate::ipv4::{self, IPType, IPv4Datagram};
+use crate::tcp::{self, TcpSegment};
+use crate::udp::{self, UdpDatagram};
 
 #[derive(Debug)]
 pub enum Unreachable {
@@ -143,20 +149,500 @@ impl From<u16> for IcmpCode {
     }
 }
 
+#[derive(Debug)]
+pub enum QuotedTransport {
+    TCP(TcpSegment),
+    // Error messages are only required to quote 8 bytes of the original
+    // datagram, which cut a TCP header short after the sequence number.
+    PartialTCP {
+        source_port: u16,
+        dest_port: u16,
+        sequence_no: u32,
+    },
+    UDP(UdpDatagram),
+    ICMP(Box<IcmpPacket>),
+    // A quoted error message, whose own quote is left alone.
+    PartialICMP {
+        message_type: u8,
+        message_code: u8,
+        checksum: u16,
+    },
+}
+
+// The start of the datagram an error message is about.
+#[derive(Debug)]
+pub struct QuotedDatagram {
+    pub header: IPv4Datagram,
+    pub transport: Option<QuotedTransport>,
+}
+
+#[derive(Debug)]
+pub struct RouterAddress {
+    pub address: Ipv4Addr,
+    pub preference: i32,
+}
+
+// One entry of an MPLS label stack (RFC 4950).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MplsLabel {
+    pub label: u32,
+    pub traffic_class: u8,
+    pub bottom_of_stack: bool,
+    pub ttl: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterfaceRole {
+    Incoming,
+    SubIpComponent,
+    Outgoing,
+    NextHop,
+}
+
+impl From<u8> for InterfaceRole {
+    fn from(raw: u8) -> Self {
+        match raw & 3 {
+            0 => Self::Incoming,
+            1 => Self::SubIpComponent,
+            2 => Self::Outgoing,
+            _ => Self::NextHop,
+        }
+    }
+}
+
+// The interface a router received or forwarded the original datagram on (RFC 5837).
+#[derive(Debug)]
+pub struct InterfaceInformation {
+    pub role: InterfaceRole,
+    pub if_index: Option<u32>,
+    pub address: Option<IpAddr>,
+    pub name: Option<String>,
+    pub mtu: Option<u32>,
+}
+
+// The interface an extended echo request asks about (RFC 8335).
+#[derive(Debug)]
+pub enum InterfaceIdentifier {
+    Name(String),
+    Index(u32),
+    Address(IpAddr),
+}
+
+#[derive(Debug)]
+pub enum ExtensionObject {
+    MplsLabelStack(Vec<MplsLabel>),
+    InterfaceInformation(InterfaceInformation),
+    InterfaceIdentification(InterfaceIdentifier),
+    Other { class: u8, c_type: u8, data: Vec<u8> },
+}
+
+// The extension structure appended to some messages (RFC 4884).
+#[derive(Debug)]
+pub struct IcmpExtensions {
+    pub version: u8,
+    pub checksum: u16,
+    pub checksum_status: ChecksumStatus,
+    pub objects: Vec<ExtensionObject>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeState {
+    Reserved,
+    Incomplete,
+    Reachable,
+    Stale,
+    Delay,
+    Probe,
+    Failed,
+    Other(u8),
+}
+
+impl From<u8> for ProbeState {
+    fn from(raw: u8) -> Self {
+        match raw {
+            0 => Self::Reserved,
+            1 => Self::Incomplete,
+            2 => Self::Reachable,
+            3 => Self::Stale,
+            4 => Self::Delay,
+            5 => Self::Probe,
+            6 => Self::Failed,
+            other => Self::Other(other),
+        }
+    }
+}
+
+// A malformed quote or extension structure is kept as its error, so the rest
+// of the message is still decoded.
+#[derive(Debug)]
+pub enum IcmpMessage {
+    Echo {
+        identifier: u16,
+        sequence_no: u16,
+    },
+    DestinationUnreachable {
+        // Only set by routers for fragmentation needed (RFC 1191), zero otherwise.
+        next_hop_mtu: u16,
+        original: Result<QuotedDatagram, ParseError>,
+        extensions: Option<Result<IcmpExtensions, ParseError>>,
+    },
+    SourceQuench {
+        original: Result<QuotedDatagram, ParseError>,
+    },
+    Redirect {
+        gateway: Ipv4Addr,
+        original: Result<QuotedDatagram, ParseError>,
+    },
+    TimeExceeded {
+        original: Result<QuotedDatagram, ParseError>,
+        extensions: Option<Result<IcmpExtensions, ParseError>>,
+    },
+    ParameterProblem {
+        pointer: u8,
+        original: Result<QuotedDatagram, ParseError>,
+        extensions: Option<Result<IcmpExtensions, ParseError>>,
+    },
+    Timestamp {
+        identifier: u16,
+        sequence_no: u16,
+        // Milliseconds since midnight UT.
+        originate: u32,
+        receive: u32,
+        transmit: u32,
+    },
+    RouterAdvertisement {
+        lifetime: u16,
+        addresses: Vec<RouterAddress>,
+    },
+    RouterSolicitation,
+    ExtendedEchoRequest {
+        identifier: u16,
+        sequence_no: u8,
+        // Whether the interface is on the node receiving the request.
+        local: bool,
+        extensions: Option<Result<IcmpExtensions, ParseError>>,
+    },
+    ExtendedEchoReply {
+        identifier: u16,
+        sequence_no: u8,
+        state: ProbeState,
+        active: bool,
+        ipv4: bool,
+        ipv6: bool,
+    },
+    Other(u32),
+}
+
 #[derive(Debug)]
 pub struct IcmpPacket {
+    pub message_type: u8,
+    pub message_code: u8,
     pub code: IcmpCode,
     pub checksum: u16,
+    pub message: IcmpMessage,
+}
+
+fn parse_quoted_transport(protocol: IPType, input: &[u8]) -> Option<QuotedTransport> {
+    match protocol {
+        IPType::TCP => match tcp::parse_tcp(input) {
+            Ok((_, segment)) => Some(QuotedTransport::TCP(segment)),
+            Err(_) => Some(QuotedTransport::PartialTCP {
+                source_port: read_u16(input, 0).ok()?,
+                dest_port: read_u16(input, 2).ok()?,
+                sequence_no: read_u32(input, 4).ok()?,
+            }),
+        },
+        IPType::UDP => udp::parse_udp(input).ok().map(|(_, datagram)| QuotedTransport::UDP(datagram)),
+        // Errors are never sent about error messages (RFC 1122 3.2.2), so one
+        // quoted here is not dissected further, which also stops the recursion.
+        IPType::ICMP => match read_u8(input, 0).ok()? {
+            3 | 4 | 5 | 11 | 12 => Some(QuotedTransport::PartialICMP {
+                message_type: read_u8(input, 0).ok()?,
+                message_code: read_u8(input, 1).ok()?,
+                checksum: read_u16(input, 2).ok()?,
+            }),
+            _ => parse_icmp(input).ok().map(|(_, packet)| QuotedTransport::ICMP(Box::new(packet))),
+        },
+        _ => None,
+    }
+}
+
+// The quoted IP header has to be whole, while its payload is decoded as far
+// as the quoted bytes allow.
+fn parse_quoted_datagram(input: &[u8]) -> Result<QuotedDatagram, ParseError> {
+    let (payload, header) = ipv4::parse_ipv4(input)?;
+    let transport = if header.fragment_offset == 0 {
+        parse_quoted_transport(header.protocol, payload)
+    } else {
+        None
+    };
+    Ok(QuotedDatagram { header, transport })
+}
+
+fn bad_object(class: u8, reason: &'static str) -> ParseError {
+    ParseError::BadOption { kind: class, reason }
+}
+
+fn parse_address(class: u8, afi: u16, address: &[u8]) -> Result<IpAddr, ParseError> {
+    match (afi, address.len()) {
+        (1, 4) => Ok(IpAddr::V4(Ipv4Addr::from(read_array::<4>(address, 0)?))),
+        (2, 16) => Ok(IpAddr::V6(Ipv6Addr::from(read_array::<16>(address, 0)?))),
+        (1 | 2, _) => Err(bad_object(class, "address length does not match its family")),
+        _ => Err(bad_object(class, "unknown address family")),
+    }
+}
+
+fn parse_interface_information(class: u8, c_type: u8, body: &[u8]) -> Result<InterfaceInformation, ParseError> {
+    let truncated = || bad_object(class, "interface information runs past the end of the object");
+    let mut rest = body;
+    let if_index = if c_type & 0b1000 != 0 {
+        let if_index = read_u32(rest, 0).map_err(|_| truncated())?;
+        rest = &rest[4..];
+        Some(if_index)
+    } else {
+        None
+    };
+    let address = if c_type & 0b0100 != 0 {
+        let afi = read_u16(rest, 0).map_err(|_| truncated())?;
+        let length = match afi {
+            1 => 4,
+            2 => 16,
+            _ => return Err(bad_object(class, "unknown address family")),
+        };
+        let address = rest.get(4..4 + length).ok_or_else(truncated)?;
+        let address = parse_address(class, afi, address)?;
+        rest = &rest[4 + length..];
+        Some(address)
+    } else {
+        None
+    };
+    let name = if c_type & 0b0010 != 0 {
+        // The length octet counts itself and the padding after the name.
+        let length = read_u8(rest, 0).map_err(|_| truncated())? as usize;
+        if length == 0 || !length.is_multiple_of(4) {
+            return Err(bad_object(class, "interface name length is not a multiple of 4"));
+        }
+        let name = rest.get(1..length).ok_or_else(truncated)?;
+        let name = String::from_utf8_lossy(name).trim_end_matches('\0').to_string();
+        rest = &rest[length..];
+        Some(name)
+    } else {
+        None
+    };
+    let mtu = if c_type & 0b0001 != 0 {
+        Some(read_u32(rest, 0).map_err(|_| truncated())?)
+    } else {
+        None
+    };
+    Ok(InterfaceInformation { role: InterfaceRole::from(c_type >> 6), if_index, address, name, mtu })
+}
+
+fn parse_interface_identifier(class: u8, c_type: u8, body: &[u8]) -> Result<InterfaceIdentifier, ParseError> {
+    match c_type {
+        1 => Ok(InterfaceIdentifier::Name(String::from_utf8_lossy(body).trim_end_matches('\0').to_string())),
+        2 if body.len() == 4 => Ok(InterfaceIdentifier::Index(read_u32(body, 0)?)),
+        3 if body.len() >= 4 => {
+            let length = read_u8(body, 2)? as usize;
+            let address = body.get(4..4 + length).ok_or(bad_object(class, "address runs past the end of the object"))?;
+            Ok(InterfaceIdentifier::Address(parse_address(class, read_u16(body, 0)?, address)?))
+        }
+        _ => Err(bad_object(class, "unexpected interface identification object")),
+    }
+}
+
+fn parse_extension_object(input: &[u8]) -> Result<(&[u8], ExtensionObject), ParseError> {
+    let length = read_u16(input, 0)? as usize;
+    let class = read_u8(input, 2)?;
+    let c_type = read_u8(input, 3)?;
+    if length < 4 {
+        return Err(bad_object(class, "object length is shorter than its header"));
+    }
+    let body = input.get(4..length).ok_or(bad_object(class, "object runs past the end of the message"))?;
+    let object = match class {
+        1 if c_type == 1 => {
+            if !body.len().is_multiple_of(4) {
+                return Err(bad_object(class, "label stack length is not a multiple of 4"));
+            }
+            let labels = body
+                .chunks_exact(4)
+                .map(|entry| {
+                    let entry = u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]);
+                    MplsLabel {
+                        label: entry >> 12,
+                        traffic_class: (entry >> 9) as u8 & 7,
+                        bottom_of_stack: entry & 0x100 != 0,
+                        ttl: entry as u8,
+                    }
+                })
+                .collect();
+            ExtensionObject::MplsLabelStack(labels)
+        }
+        2 => ExtensionObject::InterfaceInformation(parse_interface_information(class, c_type, body)?),
+        3 => ExtensionObject::InterfaceIdentification(parse_interface_identifier(class, c_type, body)?),
+        _ => ExtensionObject::Other { class, c_type, data: body.to_vec() },
+    };
+    Ok((&input[length..], object))
+}
+
+fn parse_extensions(input: &[u8]) -> Result<IcmpExtensions, ParseError> {
+    ensure_length(input, 4)?;
+    let version = read_u8(input, 0)? >> 4;
+    if version != 2 {
+        return Err(ParseError::InvalidValue { field: "icmp.extension_version", value: version as u32 });
+    }
+    let checksum = read_u16(input, 2)?;
+    // A zero checksum means the sender did not compute one.
+    let checksum_status = match checksum {
+        0 => ChecksumStatus::NotPresent,
+        _ => checksum_status(ones_complement_sum(0, input), checksum),
+    };
+    let mut rest = &input[4..];
+    let mut objects: Vec<ExtensionObject> = vec![];
+    while !rest.is_empty() {
+        let (r, object) = parse_extension_object(rest)?;
+        rest = r;
+        objects.push(object);
+    }
+    Ok(IcmpExtensions { version, checksum, checksum_status, objects })
+}
+
+// The original datagram is padded to at least 128 bytes when extensions follow it.
+const MIN_ORIGINAL_LENGTH: usize = 128;
+
+// The quoted bytes, the datagram decoded from them and the extensions.
+type ErrorBody<'a> = (&'a [u8], Result<QuotedDatagram, ParseError>, Option<Result<IcmpExtensions, ParseError>>);
+
+// Splits the body of an error message into the quoted datagram and the
+// extensions after it. `length` is the length of the original datagram in
+// 32 bit words as given in the header (RFC 4884), or zero if the sender does
+// not set it.
+fn parse_error_body(length: u8, input: &[u8]) -> ErrorBody<'_> {
+    let length = length as usize * 4;
+    if length > 0 && input.len() > length {
+        let (quoted, extensions) = input.split_at(length);
+        return (quoted, parse_quoted_datagram(quoted), Some(parse_extensions(extensions)));
+    }
+    // Older implementations append extensions after 128 bytes of the original
+    // datagram without setting the length, so look for a valid structure there.
+    if length == 0 && input.len() > MIN_ORIGINAL_LENGTH {
+        let (quoted, extensions) = input.split_at(MIN_ORIGINAL_LENGTH);
+        if let Ok(extensions) = parse_extensions(extensions) {
+            if extensions.checksum_status == ChecksumStatus::Good {
+                return (quoted, parse_quoted_datagram(quoted), Some(Ok(extensions)));
+            }
+        }
+    }
+    (input, parse_quoted_datagram(input), None)
+}
+
+// Error messages leave the quoted datagram as the remaining input.
+fn parse_icmp_message(message_type: u8, input: &[u8]) -> Result<(&[u8], IcmpMessage), ParseError> {
+    match message_type {
+        0 | 8 => {
+            let message = IcmpMessage::Echo {
+                identifier: read_u16(input, 0)?,
+                sequence_no: read_u16(input, 2)?,
+            };
+            Ok((skip(input, 4)?, message))
+        }
+        3 => {
+            let (quoted, original, extensions) = parse_error_body(read_u8(input, 1)?, skip(input, 4)?);
+            let message = IcmpMessage::DestinationUnreachable { next_hop_mtu: read_u16(input, 2)?, original, extensions };
+            Ok((quoted, message))
+        }
+        4 => {
+            let quoted = skip(input, 4)?;
+            Ok((quoted, IcmpMessage::SourceQuench { original: parse_quoted_datagram(quoted) }))
+        }
+        5 => {
+            let quoted = skip(input, 4)?;
+            let message = IcmpMessage::Redirect {
+                gateway: Ipv4Addr::from(read_array::<4>(input, 0)?),
+                original: parse_quoted_datagram(quoted),
+            };
+            Ok((quoted, message))
+        }
+        9 => {
+            let count = read_u8(input, 0)? as usize;
+            let entry_size = read_u8(input, 1)? as usize * 4;
+            if entry_size < 8 {
+                return Err(ParseError::InvalidValue { field: "icmp.address_entry_size", value: entry_size as u32 / 4 });
+            }
+            let entries = skip(input, 4)?;
+            ensure_length(entries, count * entry_size)?;
+            let addresses = entries
+                .chunks_exact(entry_size)
+                .take(count)
+                .map(|entry| {
+                    Ok(RouterAddress {
+                        address: Ipv4Addr::from(read_array::<4>(entry, 0)?),
+                        preference: read_u32(entry, 4)? as i32,
+                    })
+                })
+                .collect::<Result<Vec<RouterAddress>, ParseError>>()?;
+            let message = IcmpMessage::RouterAdvertisement { lifetime: read_u16(input, 2)?, addresses };
+            Ok((&entries[count * entry_size..], message))
+        }
+        10 => Ok((skip(input, 4)?, IcmpMessage::RouterSolicitation)),
+        11 => {
+            let (quoted, original, extensions) = parse_error_body(read_u8(input, 1)?, skip(input, 4)?);
+            Ok((quoted, IcmpMessage::TimeExceeded { original, extensions }))
+        }
+        12 => {
+            let (quoted, original, extensions) = parse_error_body(read_u8(input, 1)?, skip(input, 4)?);
+            let message = IcmpMessage::ParameterProblem { pointer: read_u8(input, 0)?, original, extensions };
+            Ok((quoted, message))
+        }
+        13 | 14 => {
+            let message = IcmpMessage::Timestamp {
+                identifier: read_u16(input, 0)?,
+                sequence_no: read_u16(input, 2)?,
+                originate: read_u32(input, 4)?,
+                receive: read_u32(input, 8)?,
+                transmit: read_u32(input, 12)?,
+            };
+            Ok((skip(input, 16)?, message))
+        }
+        42 => {
+            let rest = skip(input, 4)?;
+            let message = IcmpMessage::ExtendedEchoRequest {
+                identifier: read_u16(input, 0)?,
+                sequence_no: read_u8(input, 2)?,
+                local: read_u8(input, 3)? & 1 != 0,
+                extensions: if rest.is_empty() { None } else { Some(parse_extensions(rest)) },
+            };
+            Ok((&[], message))
+        }
+        43 => {
+            let flags = read_u8(input, 3)?;
+            let message = IcmpMessage::ExtendedEchoReply {
+                identifier: read_u16(input, 0)?,
+                sequence_no: read_u8(input, 2)?,
+                state: ProbeState::from(flags >> 5),
+                active: flags & 0b100 != 0,
+                ipv4: flags & 0b010 != 0,
+                ipv6: flags & 0b001 != 0,
+            };
+            Ok((skip(input, 4)?, message))
+        }
+        _ => Ok((skip(input, 4)?, IcmpMessage::Other(read_u32(input, 0)?))),
+    }
 }
 
-pub fn parse_icmp(input: &[u8]) -> Result<(&[u8], IcmpPacket), TryFromSliceError> {
-    let code =  IcmpCode::from(u16::from_be_bytes(<[u8; 2]>::try_from(&input[0..2])?));
-    let checksum = u16::from_be_bytes(<[u8; 2]>::try_from(&input[2..4])?);
+pub fn parse_icmp(input: &[u8]) -> Result<(&[u8], IcmpPacket), ParseError> {
+    ensure_length(input, 8)?;
+    let type_code = read_u16(input, 0)?;
+    let [message_type, message_code] = type_code.to_be_bytes();
+    let code = IcmpCode::from(type_code);
+    let checksum = read_u16(input, 2)?;
 
-    let (_, input) = input.split_at(4);
+    let (input, message) = parse_icmp_message(message_type, skip(input, 4)?)?;
     let packet = IcmpPacket {
+            message_type,
+            message_code,
             code,
             checksum,
+            message,
         };
 
     Ok((
@@ -164,3 +650,303 @@ pub fn parse_icmp(input: &[u8]) -> Result<(&[u8], IcmpPacket), TryFromSliceError
         packet
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::internet_checksum;
+
+    fn icmp(message_type: u8, code: u8, header: [u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![message_type, code, 0, 0];
+        bytes.extend_from_slice(&header);
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    // The start of a datagram from 10.0.0.1 to 10.0.0.2, as an error message quotes it.
+    fn quoted(protocol: u8, payload: &[u8]) -> Vec<u8> {
+        let mut bytes: Vec<u8> = vec![0x45, 0];
+        bytes.extend_from_slice(&(20 + payload.len() as u16 + 100).to_be_bytes());
+        bytes.extend_from_slice(&[0, 1, 0x40, 0, 64, protocol, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2]);
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    fn parse(bytes: &[u8]) -> IcmpPacket {
+        parse_icmp(bytes).unwrap().1
+    }
+
+    fn original(packet: &IcmpPacket) -> &Result<QuotedDatagram, ParseError> {
+        match &packet.message {
+            IcmpMessage::DestinationUnreachable { original, .. }
+            | IcmpMessage::SourceQuench { original }
+            | IcmpMessage::Redirect { original, .. }
+            | IcmpMessage::TimeExceeded { original, .. }
+            | IcmpMessage::ParameterProblem { original, .. } => original,
+            message => panic!("not an error message: {:?}", message),
+        }
+    }
+
+    fn transport(packet: &IcmpPacket) -> &QuotedTransport {
+        original(packet).as_ref().unwrap().transport.as_ref().unwrap()
+    }
+
+    const TCP_HEADER: [u8; 20] = [0x9c, 0x40, 0, 80, 0, 0, 0, 42, 0, 0, 0, 0, 0x50, 0x02, 0xff, 0xff, 0, 0, 0, 0];
+
+    #[test]
+    fn echo() {
+        let bytes = icmp(8, 0, [0x12, 0x34, 0, 7], b"ping");
+        let (payload, packet) = parse_icmp(&bytes).unwrap();
+        assert!(matches!(packet.code, IcmpCode::EchoRequest));
+        assert!(matches!(packet.message, IcmpMessage::Echo { identifier: 0x1234, sequence_no: 7 }));
+        assert_eq!(payload, b"ping");
+
+        let packet = parse(&icmp(0, 0, [0x12, 0x34, 0, 7], b""));
+        assert!(matches!(packet.code, IcmpCode::EchoReply));
+    }
+
+    #[test]
+    fn redirect() {
+        let bytes = icmp(5, 1, [10, 0, 0, 254], &quoted(17, &[0x04, 0xd2, 0, 53, 0, 20, 0, 0]));
+        let packet = parse(&bytes);
+        assert!(matches!(packet.code, IcmpCode::Redirect(Redirect::Host)));
+        let IcmpMessage::Redirect { gateway, .. } = &packet.message else { panic!("{:?}", packet.message) };
+        assert_eq!(*gateway, Ipv4Addr::new(10, 0, 0, 254));
+        let QuotedTransport::UDP(datagram) = transport(&packet) else { panic!("{:?}", transport(&packet)) };
+        assert_eq!((datagram.source_port, datagram.dest_port, datagram.length), (1234, 53, 20));
+    }
+
+    #[test]
+    fn fragmentation_needed_carries_the_next_hop_mtu() {
+        let bytes = icmp(3, 4, [0, 0, 0x05, 0x78], &quoted(6, &TCP_HEADER));
+        let (payload, packet) = parse_icmp(&bytes).unwrap();
+        assert!(matches!(packet.code, IcmpCode::DestinationUnreachable(Unreachable::FragmentationRequired)));
+        let IcmpMessage::DestinationUnreachable { next_hop_mtu, .. } = &packet.message else { panic!() };
+        assert_eq!(*next_hop_mtu, 1400);
+        let header = &original(&packet).as_ref().unwrap().header;
+        assert_eq!(header.dest_addr, Ipv4Addr::new(10, 0, 0, 2));
+        let QuotedTransport::TCP(segment) = transport(&packet) else { panic!("{:?}", transport(&packet)) };
+        assert_eq!((segment.source_port, segment.dest_port, segment.sequence_no), (40000, 80, 42));
+        // The quoted datagram is left as the payload.
+        assert_eq!(payload.len(), 40);
+    }
+
+    #[test]
+    fn quoted_tcp_header_cut_short() {
+        let bytes = icmp(11, 0, [0; 4], &quoted(6, &TCP_HEADER[..8]));
+        let packet = parse(&bytes);
+        assert!(matches!(
+            transport(&packet),
+            QuotedTransport::PartialTCP { source_port: 40000, dest_port: 80, sequence_no: 42 }
+        ));
+
+        // Too little of it for even the partial header.
+        let bytes = icmp(11, 0, [0; 4], &quoted(6, &TCP_HEADER[..6]));
+        assert!(original(&parse(&bytes)).as_ref().unwrap().transport.is_none());
+    }
+
+    #[test]
+    fn quoted_icmp() {
+        // An echo request is dissected.
+        let bytes = icmp(11, 0, [0; 4], &quoted(1, &icmp(8, 0, [0, 1, 0, 2], b"")));
+        let packet = parse(&bytes);
+        let QuotedTransport::ICMP(echo) = transport(&packet) else { panic!("{:?}", transport(&packet)) };
+        assert!(matches!(echo.message, IcmpMessage::Echo { identifier: 1, sequence_no: 2 }));
+
+        // An error message is not, however deeply it nests.
+        let mut bytes = icmp(3, 3, [0; 4], &quoted(17, &[0; 8]));
+        for _ in 0..100 {
+            bytes = icmp(11, 0, [0; 4], &quoted(1, &bytes));
+        }
+        let packet = parse(&bytes);
+        assert!(matches!(
+            transport(&packet),
+            QuotedTransport::PartialICMP { message_type: 11, message_code: 0, checksum: 0 }
+        ));
+    }
+
+    #[test]
+    fn malformed_quote_keeps_the_message() {
+        let mut bytes = quoted(17, &[0; 8]);
+        bytes[0] = 0x55;
+        let packet = parse(&icmp(12, 0, [20, 0, 0, 0], &bytes));
+        let IcmpMessage::ParameterProblem { pointer, .. } = &packet.message else { panic!() };
+        assert_eq!(*pointer, 20);
+        assert_eq!(original(&packet).as_ref().unwrap_err(), &ParseError::InvalidValue { field: "ipv4.version", value: 5 });
+
+        let packet = parse(&icmp(4, 0, [0; 4], &bytes[..12]));
+        assert!(matches!(original(&packet), Err(ParseError::Truncated { .. })));
+    }
+
+    #[test]
+    fn router_advertisement() {
+        let body = [192, 168, 0, 1, 0, 0, 0, 10, 192, 168, 0, 2, 0xff, 0xff, 0xff, 0xff];
+        let packet = parse(&icmp(9, 0, [2, 2, 0x07, 0x08], &body));
+        let IcmpMessage::RouterAdvertisement { lifetime, addresses } = &packet.message else { panic!() };
+        assert_eq!(*lifetime, 1800);
+        assert_eq!(addresses.len(), 2);
+        assert_eq!(addresses[0].address, Ipv4Addr::new(192, 168, 0, 1));
+        assert_eq!(addresses[0].preference, 10);
+        assert_eq!(addresses[1].preference, -1);
+
+        // Entries must be at least the two words of an address and preference.
+        assert!(parse_icmp(&icmp(9, 0, [2, 1, 0x07, 0x08], &body)).is_err());
+        assert!(parse_icmp(&icmp(9, 0, [3, 2, 0x07, 0x08], &body)).is_err());
+    }
+
+    fn object(class: u8, c_type: u8, body: &[u8]) -> Vec<u8> {
+        let mut bytes = (4 + body.len() as u16).to_be_bytes().to_vec();
+        bytes.extend_from_slice(&[class, c_type]);
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    // An extension structure holding `objects`, with its checksum filled in.
+    fn extensions(objects: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = vec![0x20, 0, 0, 0];
+        bytes.extend(objects.concat());
+        let checksum = internet_checksum(&bytes);
+        bytes[2..4].copy_from_slice(&checksum.to_be_bytes());
+        bytes
+    }
+
+    fn objects(packet: &IcmpPacket) -> &[ExtensionObject] {
+        let extensions = match &packet.message {
+            IcmpMessage::DestinationUnreachable { extensions, .. }
+            | IcmpMessage::TimeExceeded { extensions, .. }
+            | IcmpMessage::ParameterProblem { extensions, .. }
+            | IcmpMessage::ExtendedEchoRequest { extensions, .. } => extensions,
+            message => panic!("no extensions: {:?}", message),
+        };
+        let extensions = extensions.as_ref().unwrap().as_ref().unwrap();
+        assert_eq!(extensions.checksum_status, ChecksumStatus::Good);
+        &extensions.objects
+    }
+
+    // A time exceeded message quoting a 128 byte UDP datagram, with the length
+    // field set as RFC 4884 asks.
+    fn time_exceeded(extensions: &[u8]) -> Vec<u8> {
+        let mut body = quoted(17, &[0; 108]);
+        body.extend_from_slice(extensions);
+        icmp(11, 0, [0, 32, 0, 0], &body)
+    }
+
+    #[test]
+    fn mpls_label_stack() {
+        let entries = [0x03, 0xe8, 0x0b, 0x01, 0xff, 0xff, 0xf0, 0xff];
+        let packet = parse(&time_exceeded(&extensions(&[object(1, 1, &entries)])));
+        let [ExtensionObject::MplsLabelStack(labels)] = objects(&packet) else { panic!("{:?}", packet.message) };
+        assert_eq!(
+            labels,
+            &[
+                MplsLabel { label: 16000, traffic_class: 5, bottom_of_stack: true, ttl: 1 },
+                MplsLabel { label: 0xfffff, traffic_class: 0, bottom_of_stack: false, ttl: 255 },
+            ]
+        );
+
+        let bytes = time_exceeded(&extensions(&[object(1, 1, &entries[..6])]));
+        let IcmpMessage::TimeExceeded { extensions: Some(Err(error)), .. } = parse(&bytes).message else { panic!() };
+        assert_eq!(error, bad_object(1, "label stack length is not a multiple of 4"));
+    }
+
+    #[test]
+    fn interface_information_fields() {
+        // Outgoing interface; every combination of ifIndex, address, name and MTU.
+        for fields in 0..16u8 {
+            let mut body: Vec<u8> = vec![];
+            if fields & 0b1000 != 0 {
+                body.extend_from_slice(&[0, 0, 0, 7]);
+            }
+            if fields & 0b0100 != 0 {
+                body.extend_from_slice(&[0, 1, 0, 0, 192, 0, 2, 1]);
+            }
+            if fields & 0b0010 != 0 {
+                body.extend_from_slice(b"\x08eth0\0\0\0");
+            }
+            if fields & 0b0001 != 0 {
+                body.extend_from_slice(&[0, 0, 0x05, 0xdc]);
+            }
+            let packet = parse(&time_exceeded(&extensions(&[object(2, 0b1000_0000 | fields, &body)])));
+            let [ExtensionObject::InterfaceInformation(info)] = objects(&packet) else { panic!("{:?}", packet.message) };
+            assert_eq!(info.role, InterfaceRole::Outgoing);
+            assert_eq!(info.if_index, (fields & 0b1000 != 0).then_some(7));
+            assert_eq!(info.address, (fields & 0b0100 != 0).then_some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))));
+            assert_eq!(info.name.as_deref(), (fields & 0b0010 != 0).then_some("eth0"));
+            assert_eq!(info.mtu, (fields & 0b0001 != 0).then_some(1500));
+        }
+
+        // The c_type promises an MTU the object does not have room for.
+        let bytes = time_exceeded(&extensions(&[object(2, 0b1000_1001, &[0, 0, 0, 7])]));
+        let IcmpMessage::TimeExceeded { extensions: Some(Err(error)), .. } = parse(&bytes).message else { panic!() };
+        assert_eq!(error, bad_object(2, "interface information runs past the end of the object"));
+    }
+
+    #[test]
+    fn extensions_after_the_length_field_or_128_bytes() {
+        let structure = extensions(&[object(1, 1, &[0x03, 0xe8, 0x0b, 0x01])]);
+
+        // The length field can leave the quote shorter than 128 bytes.
+        let mut body = quoted(17, &[0; 8]);
+        body.extend_from_slice(&structure);
+        let bytes = icmp(3, 3, [0, 7, 0, 0], &body);
+        let (payload, packet) = parse_icmp(&bytes).unwrap();
+        assert_eq!(payload.len(), 28);
+        assert_eq!(objects(&packet).len(), 1);
+
+        // Without it, a structure with a good checksum is looked for after 128 bytes.
+        let mut bytes = time_exceeded(&structure);
+        bytes[5] = 0;
+        let (payload, packet) = parse_icmp(&bytes).unwrap();
+        assert_eq!(payload.len(), 128);
+        assert_eq!(objects(&packet).len(), 1);
+
+        // Anything else there is just more of the quote.
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let (payload, packet) = parse_icmp(&bytes).unwrap();
+        assert_eq!(payload.len(), 128 + structure.len());
+        assert!(matches!(packet.message, IcmpMessage::TimeExceeded { original: Ok(_), extensions: None }));
+
+        // A bad structure where the length field points is kept as an error.
+        let mut bytes = time_exceeded(&structure);
+        bytes[8 + 128] = 0x10;
+        let IcmpMessage::TimeExceeded { extensions: Some(Err(error)), .. } = parse(&bytes).message else { panic!() };
+        assert_eq!(error, ParseError::InvalidValue { field: "icmp.extension_version", value: 1 });
+    }
+
+    #[test]
+    fn extended_echo() {
+        let name = extensions(&[object(3, 1, b"eth0")]);
+        let packet = parse(&icmp(42, 0, [0x12, 0x34, 9, 1], &name));
+        let IcmpMessage::ExtendedEchoRequest { identifier: 0x1234, sequence_no: 9, local: true, .. } = packet.message
+        else {
+            panic!("{:?}", packet.message)
+        };
+        let [ExtensionObject::InterfaceIdentification(InterfaceIdentifier::Name(name))] = objects(&packet) else {
+            panic!()
+        };
+        assert_eq!(name, "eth0");
+
+        let address = extensions(&[object(3, 3, &[0, 2, 16, 0, 0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1])]);
+        let packet = parse(&icmp(42, 0, [0x12, 0x34, 10, 0], &address));
+        assert!(matches!(packet.message, IcmpMessage::ExtendedEchoRequest { local: false, .. }));
+        let [ExtensionObject::InterfaceIdentification(InterfaceIdentifier::Address(address))] = objects(&packet) else {
+            panic!()
+        };
+        assert_eq!(address, &"2001:db8::1".parse::<IpAddr>().unwrap());
+
+        for (flags, state, active, ipv4, ipv6) in [
+            (0b0100_0110, ProbeState::Reachable, true, true, false),
+            (0b1100_0001, ProbeState::Failed, false, false, true),
+            (0b1110_0101, ProbeState::Other(7), true, false, true),
+        ] {
+            let packet = parse(&icmp(43, 0, [0x12, 0x34, 9, flags], b""));
+            let IcmpMessage::ExtendedEchoReply { identifier: 0x1234, sequence_no: 9, state: s, active: a, ipv4: v4, ipv6: v6 } =
+                packet.message
+            else {
+                panic!("{:?}", packet.message)
+            };
+            assert_eq!((s, a, v4, v6), (state, active, ipv4, ipv6));
+        }
+    }
+}
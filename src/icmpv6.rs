@@ -0,0 +1,311 @@
+use std::net::Ipv6Addr;
+use crate::bytes::{ensure_length, read_array, read_u16, read_u32, read_u8, skip};
+use crate::error::ParseError;
+
+#[derive(Debug)]
+pub enum Unreachable {
+    NoRoute,
+    AdministrativelyProhibited,
+    BeyondScopeOfSourceAddress,
+    AddressUnreachable,
+    PortUnreachable,
+    SourceAddressFailedPolicy,
+    RejectRoute,
+    SourceRoutingHeaderError,
+}
+
+#[derive(Debug)]
+pub enum TimeExceeded {
+    HopLimit,
+    FragmentReassembly,
+}
+
+#[derive(Debug)]
+pub enum ParameterProblem {
+    ErroneousHeaderField,
+    UnrecognizedNextHeader,
+    UnrecognizedOption,
+}
+
+#[derive(Debug)]
+pub enum Icmpv6Code {
+    DestinationUnreachable(Unreachable),
+    PacketTooBig,
+    TimeExceeded(TimeExceeded),
+    ParameterProblem(ParameterProblem),
+    EchoRequest,
+    EchoReply,
+    RouterSolicitation,
+    RouterAdvertisement,
+    NeighborSolicitation,
+    NeighborAdvertisement,
+    Redirect,
+    Other(u16),
+}
+
+impl From<u16> for Icmpv6Code {
+    fn from(raw: u16) -> Self {
+        let [t, c] = raw.to_be_bytes();
+        match t {
+            1 => match c {
+                0 => Self::DestinationUnreachable(Unreachable::NoRoute),
+                1 => Self::DestinationUnreachable(Unreachable::AdministrativelyProhibited),
+                2 => Self::DestinationUnreachable(Unreachable::BeyondScopeOfSourceAddress),
+                3 => Self::DestinationUnreachable(Unreachable::AddressUnreachable),
+                4 => Self::DestinationUnreachable(Unreachable::PortUnreachable),
+                5 => Self::DestinationUnreachable(Unreachable::SourceAddressFailedPolicy),
+                6 => Self::DestinationUnreachable(Unreachable::RejectRoute),
+                7 => Self::DestinationUnreachable(Unreachable::SourceRoutingHeaderError),
+                _ => Self::Other(raw),
+            },
+            2 => match c {
+                0 => Self::PacketTooBig,
+                _ => Self::Other(raw),
+            },
+            3 => match c {
+                0 => Self::TimeExceeded(TimeExceeded::HopLimit),
+                1 => Self::TimeExceeded(TimeExceeded::FragmentReassembly),
+                _ => Self::Other(raw),
+            },
+            4 => match c {
+                0 => Self::ParameterProblem(ParameterProblem::ErroneousHeaderField),
+                1 => Self::ParameterProblem(ParameterProblem::UnrecognizedNextHeader),
+                2 => Self::ParameterProblem(ParameterProblem::UnrecognizedOption),
+                _ => Self::Other(raw),
+            },
+            128 => Self::EchoRequest,
+            129 => Self::EchoReply,
+            133 => Self::RouterSolicitation,
+            134 => Self::RouterAdvertisement,
+            135 => Self::NeighborSolicitation,
+            136 => Self::NeighborAdvertisement,
+            137 => Self::Redirect,
+            _ => Self::Other(raw),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum NdpOption {
+    SourceLinkLayerAddress(Vec<u8>),
+    TargetLinkLayerAddress(Vec<u8>),
+    PrefixInformation {
+        prefix_length: u8,
+        on_link: bool,
+        autonomous: bool,
+        valid_lifetime: u32,
+        preferred_lifetime: u32,
+        prefix: Ipv6Addr,
+    },
+    RedirectedHeader(Vec<u8>),
+    Mtu(u32),
+    RecursiveDnsServer {
+        lifetime: u32,
+        servers: Vec<Ipv6Addr>,
+    },
+    Other(u8, Vec<u8>),
+    // An option that failed to parse, at its offset from the start of the options.
+    // Nothing after it is decoded.
+    BadOption { offset: usize, error: ParseError },
+}
+
+#[derive(Debug)]
+pub enum Icmpv6Message {
+    Unused,
+    PacketTooBig {
+        mtu: u32,
+    },
+    ParameterProblem {
+        pointer: u32,
+    },
+    Echo {
+        identifier: u16,
+        sequence_no: u16,
+    },
+    RouterSolicitation {
+        options: Vec<NdpOption>,
+    },
+    RouterAdvertisement {
+        cur_hop_limit: u8,
+        managed: bool,
+        other_config: bool,
+        router_lifetime: u16,
+        reachable_time: u32,
+        retrans_timer: u32,
+        options: Vec<NdpOption>,
+    },
+    NeighborSolicitation {
+        target: Ipv6Addr,
+        options: Vec<NdpOption>,
+    },
+    NeighborAdvertisement {
+        router: bool,
+        solicited: bool,
+        override_entry: bool,
+        target: Ipv6Addr,
+        options: Vec<NdpOption>,
+    },
+    Redirect {
+        target: Ipv6Addr,
+        destination: Ipv6Addr,
+        options: Vec<NdpOption>,
+    },
+    Other(u32),
+}
+
+#[derive(Debug)]
+pub struct Icmpv6Packet {
+    pub message_type: u8,
+    pub message_code: u8,
+    pub code: Icmpv6Code,
+    pub checksum: u16,
+    pub message: Icmpv6Message,
+}
+
+fn parse_ndp_option(input: &[u8]) -> Result<(&[u8], NdpOption), ParseError> {
+    let option_type = read_u8(input, 0)?;
+    let length = read_u8(input, 1)? as usize * 8;
+    if length == 0 {
+        return Err(ParseError::BadOption { kind: option_type, reason: "option length is zero" });
+    }
+    let body = input.get(2..length).ok_or(ParseError::BadOption {
+        kind: option_type,
+        reason: "option runs past the end of the message",
+    })?;
+    let option = match option_type {
+        1 => NdpOption::SourceLinkLayerAddress(body.to_vec()),
+        2 => NdpOption::TargetLinkLayerAddress(body.to_vec()),
+        3 => {
+            if length != 32 {
+                return Err(ParseError::BadOption { kind: option_type, reason: "unexpected option length" });
+            }
+            let flags = read_u8(body, 1)?;
+            NdpOption::PrefixInformation {
+                prefix_length: read_u8(body, 0)?,
+                on_link: flags & 0b1000_0000 != 0,
+                autonomous: flags & 0b0100_0000 != 0,
+                valid_lifetime: read_u32(body, 2)?,
+                preferred_lifetime: read_u32(body, 6)?,
+                prefix: Ipv6Addr::from(read_array::<16>(body, 14)?),
+            }
+        }
+        4 => NdpOption::RedirectedHeader(body[6..].to_vec()),
+        5 => {
+            if length != 8 {
+                return Err(ParseError::BadOption { kind: option_type, reason: "unexpected option length" });
+            }
+            NdpOption::Mtu(read_u32(body, 2)?)
+        }
+        25 => {
+            if length < 24 {
+                return Err(ParseError::BadOption { kind: option_type, reason: "unexpected option length" });
+            }
+            let servers = body[6..]
+                .chunks_exact(16)
+                .filter_map(|address| <[u8; 16]>::try_from(address).ok())
+                .map(Ipv6Addr::from)
+                .collect();
+            NdpOption::RecursiveDnsServer { lifetime: read_u32(body, 2)?, servers }
+        }
+        other => NdpOption::Other(other, body.to_vec()),
+    };
+    Ok((&input[length..], option))
+}
+
+fn parse_ndp_options(input: &[u8]) -> Vec<NdpOption> {
+    let mut rest = input;
+    let mut options: Vec<NdpOption> = vec![];
+    while !rest.is_empty() {
+        let (r, option) = match parse_ndp_option(rest) {
+            Ok(parsed) => parsed,
+            Err(error) => {
+                options.push(NdpOption::BadOption { offset: input.len() - rest.len(), error });
+                break;
+            }
+        };
+        rest = r;
+        options.push(option);
+    }
+    options
+}
+
+fn parse_icmpv6_message(message_type: u8, input: &[u8]) -> Result<(&[u8], Icmpv6Message), ParseError> {
+    match message_type {
+        1 | 3 => Ok((skip(input, 4)?, Icmpv6Message::Unused)),
+        2 => Ok((skip(input, 4)?, Icmpv6Message::PacketTooBig { mtu: read_u32(input, 0)? })),
+        4 => Ok((skip(input, 4)?, Icmpv6Message::ParameterProblem { pointer: read_u32(input, 0)? })),
+        128 | 129 => {
+            let message = Icmpv6Message::Echo {
+                identifier: read_u16(input, 0)?,
+                sequence_no: read_u16(input, 2)?,
+            };
+            Ok((skip(input, 4)?, message))
+        }
+        133 => {
+            let options = parse_ndp_options(skip(input, 4)?);
+            Ok((&[], Icmpv6Message::RouterSolicitation { options }))
+        }
+        134 => {
+            let flags = read_u8(input, 1)?;
+            let message = Icmpv6Message::RouterAdvertisement {
+                cur_hop_limit: read_u8(input, 0)?,
+                managed: flags & 0b1000_0000 != 0,
+                other_config: flags & 0b0100_0000 != 0,
+                router_lifetime: read_u16(input, 2)?,
+                reachable_time: read_u32(input, 4)?,
+                retrans_timer: read_u32(input, 8)?,
+                options: parse_ndp_options(skip(input, 12)?),
+            };
+            Ok((&[], message))
+        }
+        135 => {
+            let message = Icmpv6Message::NeighborSolicitation {
+                target: Ipv6Addr::from(read_array::<16>(input, 4)?),
+                options: parse_ndp_options(skip(input, 20)?),
+            };
+            Ok((&[], message))
+        }
+        136 => {
+            let flags = read_u8(input, 0)?;
+            let message = Icmpv6Message::NeighborAdvertisement {
+                router: flags & 0b1000_0000 != 0,
+                solicited: flags & 0b0100_0000 != 0,
+                override_entry: flags & 0b0010_0000 != 0,
+                target: Ipv6Addr::from(read_array::<16>(input, 4)?),
+                options: parse_ndp_options(skip(input, 20)?),
+            };
+            Ok((&[], message))
+        }
+        137 => {
+            let message = Icmpv6Message::Redirect {
+                target: Ipv6Addr::from(read_array::<16>(input, 4)?),
+                destination: Ipv6Addr::from(read_array::<16>(input, 20)?),
+                options: parse_ndp_options(skip(input, 36)?),
+            };
+            Ok((&[], message))
+        }
+        _ => Ok((skip(input, 4)?, Icmpv6Message::Other(read_u32(input, 0)?))),
+    }
+}
+
+pub fn parse_icmpv6(input: &[u8]) -> Result<(&[u8], Icmpv6Packet), ParseError> {
+    ensure_length(input, 8)?;
+    let type_code = read_u16(input, 0)?;
+    let [message_type, message_code] = type_code.to_be_bytes();
+    let code = Icmpv6Code::from(type_code);
+    let checksum = read_u16(input, 2)?;
+
+    let (input, message) = parse_icmpv6_message(message_type, skip(input, 4)?)?;
+    let packet = Icmpv6Packet {
+            message_type,
+            message_code,
+            code,
+            checksum,
+            message,
+        };
+
+    Ok((
+        input,
+        packet
+    ))
+}
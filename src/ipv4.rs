@@ -1,20 +1,93 @@
-use std::array::TryFromSliceError;
-use std::convert::TryFrom;
 use std::net::Ipv4Addr;
+use crate::checksum::{checksum_status, ones_complement_sum, ChecksumStatus};
+use crate::bytes::{ensure_length, read_array, read_u16, read_u32, read_u8};
+use crate::error::ParseError;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum IPType {
     ICMP,
     TCP,
     UDP,
+    ICMPv6,
     Other(u8),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ecn {
+    NotEct,
+    Ect1,
+    Ect0,
+    CongestionExperienced,
+}
+
+impl From<u8> for Ecn {
+    fn from(raw: u8) -> Self {
+        match raw & 0b11 {
+            0 => Ecn::NotEct,
+            1 => Ecn::Ect1,
+            2 => Ecn::Ect0,
+            _ => Ecn::CongestionExperienced,
+        }
+    }
+}
+
 #[derive(Debug)]
+pub enum Ipv4OptionType {
+    EndOfOptionList,
+    NoOperation,
+    Security,
+    LooseSourceRoute,
+    Timestamp,
+    RecordRoute,
+    StrictSourceRoute,
+    RouterAlert,
+    Other(u8),
+}
+
+impl From<u8> for Ipv4OptionType {
+    fn from(raw: u8) -> Self {
+        match raw {
+            0 => Ipv4OptionType::EndOfOptionList,
+            1 => Ipv4OptionType::NoOperation,
+            130 => Ipv4OptionType::Security,
+            131 => Ipv4OptionType::LooseSourceRoute,
+            68 => Ipv4OptionType::Timestamp,
+            7 => Ipv4OptionType::RecordRoute,
+            137 => Ipv4OptionType::StrictSourceRoute,
+            148 => Ipv4OptionType::RouterAlert,
+            other => Ipv4OptionType::Other(other),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimestampEntry {
+    pub address: Option<Ipv4Addr>,
+    pub timestamp: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ipv4Option {
+    EndOfOptionList,
+    NoOperation,
+    Security { classification: u8, protection_authority: Vec<u8> },
+    LooseSourceRoute { pointer: u8, route: Vec<Ipv4Addr> },
+    Timestamp { pointer: u8, overflow: u8, flag: u8, entries: Vec<TimestampEntry> },
+    RecordRoute { pointer: u8, route: Vec<Ipv4Addr> },
+    StrictSourceRoute { pointer: u8, route: Vec<Ipv4Addr> },
+    RouterAlert(u16),
+    Other(u8, Vec<u8>),
+    // An option that failed to parse, at its offset from the start of the options.
+    // Nothing after it is decoded.
+    BadOption { offset: usize, error: ParseError },
+}
+
+#[derive(Debug, Clone)]
 pub struct IPv4Datagram {
     pub version: u8,
     pub header_length: u8,
-    pub type_of_service: u8,
+    pub dscp: u8,
+    pub ecn: Ecn,
     pub length: u16,
     pub id: u16,
     pub flags: u8,
@@ -22,8 +95,16 @@ pub struct IPv4Datagram {
     pub ttl: u8,
     pub protocol: IPType,
     pub header_checksum: u16,
+    pub checksum_status: ChecksumStatus,
     pub source_addr: Ipv4Addr,
     pub dest_addr: Ipv4Addr,
+    pub options: Option<Vec<Ipv4Option>>,
+}
+
+impl IPv4Datagram {
+    pub fn is_fragment(&self) -> bool {
+        self.flags & 0b001 != 0 || self.fragment_offset != 0
+    }
 }
 
 impl From<u8> for IPType {
@@ -32,37 +113,175 @@ impl From<u8> for IPType {
             1 => IPType::ICMP,
             6 => IPType::TCP,
             17 => IPType::UDP,
+            58 => IPType::ICMPv6,
             other => IPType::Other(other),
         }
     }
 }
 
-pub fn parse_ipv4(input: &[u8]) -> Result<(&[u8], IPv4Datagram), TryFromSliceError> {
-    let version_header_length = <u8>::try_from(input[0])?;
+fn parse_route(kind: u8, body: &[u8]) -> Result<(u8, Vec<Ipv4Addr>), ParseError> {
+    let pointer = read_u8(body, 0)?;
+    if body.len() % 4 != 1 {
+        return Err(ParseError::BadOption { kind, reason: "route length is not a whole number of addresses" });
+    }
+    if pointer < 4 {
+        return Err(ParseError::BadOption { kind, reason: "route pointer points into the option header" });
+    }
+    let route = body[1..]
+        .chunks_exact(4)
+        .filter_map(|address| <[u8; 4]>::try_from(address).ok())
+        .map(Ipv4Addr::from)
+        .collect();
+    Ok((pointer, route))
+}
+
+fn parse_timestamp(kind: u8, body: &[u8]) -> Result<Ipv4Option, ParseError> {
+    let pointer = read_u8(body, 0)?;
+    let overflow_flag = read_u8(body, 1)?;
+    let overflow = overflow_flag >> 4;
+    let flag = overflow_flag & 0b1111;
+    let entry_length = match flag {
+        0 => 4,
+        1 | 3 => 8,
+        _ => return Err(ParseError::BadOption { kind, reason: "unknown timestamp flag" }),
+    };
+    if !(body.len() - 2).is_multiple_of(entry_length) {
+        return Err(ParseError::BadOption { kind, reason: "timestamp length is not a whole number of entries" });
+    }
+    let mut entries: Vec<TimestampEntry> = vec![];
+    for entry in body[2..].chunks_exact(entry_length) {
+        entries.push(match entry_length {
+            4 => TimestampEntry { address: None, timestamp: read_u32(entry, 0)? },
+            _ => TimestampEntry {
+                address: Some(Ipv4Addr::from(read_array::<4>(entry, 0)?)),
+                timestamp: read_u32(entry, 4)?,
+            },
+        });
+    }
+    Ok(Ipv4Option::Timestamp { pointer, overflow, flag, entries })
+}
+
+fn parse_ipv4_option(input: &[u8]) -> Result<(&[u8], Ipv4Option), ParseError> {
+    let kind = read_u8(input, 0)?;
+    let option_type = Ipv4OptionType::from(kind);
+    match option_type {
+        Ipv4OptionType::EndOfOptionList => return Ok((&input[1..], Ipv4Option::EndOfOptionList)),
+        Ipv4OptionType::NoOperation => return Ok((&input[1..], Ipv4Option::NoOperation)),
+        _ => {}
+    }
+    let length = read_u8(input, 1).map_err(|_| ParseError::BadOption { kind, reason: "missing option length" })?;
+    if length < 2 {
+        return Err(ParseError::BadOption { kind, reason: "option length shorter than its header" });
+    }
+    let body = input.get(2..length as usize).ok_or(ParseError::BadOption {
+        kind,
+        reason: "option runs past the end of the header",
+    })?;
+    let option = match option_type {
+        Ipv4OptionType::Security => {
+            let classification = read_u8(body, 0)
+                .map_err(|_| ParseError::BadOption { kind, reason: "missing classification level" })?;
+            Ipv4Option::Security { classification, protection_authority: body[1..].to_vec() }
+        }
+        Ipv4OptionType::LooseSourceRoute => {
+            let (pointer, route) = parse_route(kind, body)?;
+            Ipv4Option::LooseSourceRoute { pointer, route }
+        }
+        Ipv4OptionType::Timestamp => {
+            if body.len() < 2 {
+                return Err(ParseError::BadOption { kind, reason: "option length shorter than its header" });
+            }
+            parse_timestamp(kind, body)?
+        }
+        Ipv4OptionType::RecordRoute => {
+            let (pointer, route) = parse_route(kind, body)?;
+            Ipv4Option::RecordRoute { pointer, route }
+        }
+        Ipv4OptionType::StrictSourceRoute => {
+            let (pointer, route) = parse_route(kind, body)?;
+            Ipv4Option::StrictSourceRoute { pointer, route }
+        }
+        Ipv4OptionType::RouterAlert => {
+            if length != 4 {
+                return Err(ParseError::BadOption { kind, reason: "router alert must be 4 bytes long" });
+            }
+            Ipv4Option::RouterAlert(read_u16(body, 0)?)
+        }
+        _ => Ipv4Option::Other(kind, body.to_vec()),
+    };
+    Ok((&input[length as usize..], option))
+}
+
+fn parse_ipv4_options(input: &[u8]) -> Vec<Ipv4Option> {
+    let mut rest = input;
+    let mut options: Vec<Ipv4Option> = vec![];
+    while !rest.is_empty() {
+        let (r, option) = match parse_ipv4_option(rest) {
+            Ok(parsed) => parsed,
+            Err(error) => {
+                options.push(Ipv4Option::BadOption { offset: input.len() - rest.len(), error });
+                break;
+            }
+        };
+        rest = r;
+        let end = option == Ipv4Option::EndOfOptionList;
+        options.push(option);
+        if end {
+            break;
+        }
+    }
+    options
+}
+
+impl From<IPType> for u8 {
+    fn from(protocol: IPType) -> Self {
+        match protocol {
+            IPType::ICMP => 1,
+            IPType::TCP => 6,
+            IPType::UDP => 17,
+            IPType::ICMPv6 => 58,
+            IPType::Other(other) => other,
+        }
+    }
+}
+
+pub fn parse_ipv4(input: &[u8]) -> Result<(&[u8], IPv4Datagram), ParseError> {
+    ensure_length(input, 20)?;
+    let version_header_length = read_u8(input, 0)?;
     let version = version_header_length >> 4;
     let header_length = version_header_length & 15;
-    let type_of_service = <u8>::try_from(input[1])?;
-    let length =  u16::from_be_bytes(<[u8; 2]>::try_from(&input[2..4])?);
-    let id =  u16::from_be_bytes(<[u8; 2]>::try_from(&input[4..6])?);
-    let flag_frag_offset =  u16::from_be_bytes(<[u8; 2]>::try_from(&input[6..8])?);
+    if version != 4 {
+        return Err(ParseError::InvalidValue { field: "ipv4.version", value: version as u32 });
+    }
+    if header_length < 5 {
+        return Err(ParseError::BadHeaderLength { protocol: "IPv4", length: header_length as usize * 4 });
+    }
+    let type_of_service = read_u8(input, 1)?;
+    let length = read_u16(input, 2)?;
+    let id = read_u16(input, 4)?;
+    let flag_frag_offset = read_u16(input, 6)?;
     let flags = (flag_frag_offset >> 13) as u8;
     let fragment_offset = flag_frag_offset & 8191;
-    let ttl = <u8>::try_from(input[8])?;
-    let protocol = IPType::from(<u8>::try_from(input[9])?);
-    let header_checksum =  u16::from_be_bytes(<[u8; 2]>::try_from(&input[10..12])?);
-    let source_addr = Ipv4Addr::from(<[u8; 4]>::try_from(&input[12..16])?);
-    let dest_addr = Ipv4Addr::from(<[u8; 4]>::try_from(&input[16..20])?);
-
-    let (_, input) = if header_length > 5 {
-        input.split_at(20 + ((header_length - 5) * 4) as usize)
+    let ttl = read_u8(input, 8)?;
+    let protocol = IPType::from(read_u8(input, 9)?);
+    let header_checksum = read_u16(input, 10)?;
+    let source_addr = Ipv4Addr::from(read_array::<4>(input, 12)?);
+    let dest_addr = Ipv4Addr::from(read_array::<4>(input, 16)?);
+
+    ensure_length(input, header_length as usize * 4)?;
+    let (header, input) = input.split_at(header_length as usize * 4);
+    let checksum_status = checksum_status(ones_complement_sum(0, header), header_checksum);
+    let options = if header_length > 5 {
+        Some(parse_ipv4_options(&header[20..]))
     } else {
-        input.split_at(20)
+        None
     };
 
     let diagram = IPv4Datagram {
         version,
         header_length,
-        type_of_service,
+        dscp: type_of_service >> 2,
+        ecn: Ecn::from(type_of_service),
         length,
         id,
         flags,
@@ -70,8 +289,10 @@ pub fn parse_ipv4(input: &[u8]) -> Result<(&[u8], IPv4Datagram), TryFromSliceErr
         ttl,
         protocol,
         header_checksum,
+        checksum_status,
         source_addr,
         dest_addr,
+        options,
     };
     Ok((input, diagram))
 }
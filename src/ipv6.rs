@@ -0,0 +1,193 @@
+use std::net::Ipv6Addr;
+use crate::bytes::{ensure_length, read_array, read_u16, read_u32, read_u8, skip};
+use crate::error::ParseError;
+use crate::ipv4::{Ecn, IPType};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ipv6Option {
+    Pad1,
+    PadN(u8),
+    RouterAlert(u16),
+    Jumbo(u32),
+    Other(u8, Vec<u8>),
+    // An option that failed to parse, at its offset from the start of the options.
+    // Nothing after it is decoded.
+    BadOption { offset: usize, error: ParseError },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtensionHeader {
+    HopByHop(Vec<Ipv6Option>),
+    Routing {
+        routing_type: u8,
+        segments_left: u8,
+        data: Vec<u8>,
+    },
+    Fragment {
+        fragment_offset: u16,
+        more_fragments: bool,
+        id: u32,
+    },
+    DestinationOptions(Vec<Ipv6Option>),
+    Authentication {
+        spi: u32,
+        sequence_no: u32,
+        icv: Vec<u8>,
+    },
+}
+
+#[derive(Debug)]
+pub struct IPv6Datagram {
+    pub version: u8,
+    pub dscp: u8,
+    pub ecn: Ecn,
+    pub flow_label: u32,
+    pub payload_length: u16,
+    pub next_header: u8,
+    pub hop_limit: u8,
+    pub source_addr: Ipv6Addr,
+    pub dest_addr: Ipv6Addr,
+    pub extension_headers: Vec<ExtensionHeader>,
+    // Total length of the extension headers, in bytes.
+    pub extension_length: usize,
+    pub protocol: IPType,
+}
+
+impl IPv6Datagram {
+    pub fn is_fragment(&self) -> bool {
+        self.extension_headers.iter().any(|header| {
+            matches!(header, ExtensionHeader::Fragment { fragment_offset, more_fragments, .. }
+                if *fragment_offset != 0 || *more_fragments)
+        })
+    }
+}
+
+fn parse_ipv6_option(input: &[u8]) -> Result<(&[u8], Ipv6Option), ParseError> {
+    let option_type = read_u8(input, 0)?;
+    if option_type == 0 {
+        return Ok((&input[1..], Ipv6Option::Pad1));
+    }
+    let length = read_u8(input, 1)?;
+    let data = input.get(2..2 + length as usize).ok_or(ParseError::BadOption {
+        kind: option_type,
+        reason: "option runs past the end of its header",
+    })?;
+    let option = match (option_type, length) {
+        (1, _) => Ipv6Option::PadN(length),
+        (5, 2) => Ipv6Option::RouterAlert(read_u16(data, 0)?),
+        (0xC2, 4) => Ipv6Option::Jumbo(read_u32(data, 0)?),
+        (5, _) | (0xC2, _) => {
+            return Err(ParseError::BadOption { kind: option_type, reason: "unexpected option length" })
+        }
+        (other, _) => Ipv6Option::Other(other, data.to_vec()),
+    };
+    Ok((&input[2 + length as usize..], option))
+}
+
+fn parse_ipv6_options(input: &[u8]) -> Vec<Ipv6Option> {
+    let mut rest = input;
+    let mut options: Vec<Ipv6Option> = vec![];
+    while !rest.is_empty() {
+        let (r, option) = match parse_ipv6_option(rest) {
+            Ok(parsed) => parsed,
+            Err(error) => {
+                options.push(Ipv6Option::BadOption { offset: input.len() - rest.len(), error });
+                break;
+            }
+        };
+        rest = r;
+        options.push(option);
+    }
+    options
+}
+
+fn parse_extension_header(next_header: u8, input: &[u8]) -> Result<(&[u8], u8, ExtensionHeader), ParseError> {
+    let following_header = read_u8(input, 0)?;
+    let length = match next_header {
+        51 => (read_u8(input, 1)? as usize + 2) * 4,
+        44 => 8,
+        _ => (read_u8(input, 1)? as usize + 1) * 8,
+    };
+    ensure_length(input, length)?;
+    let (body, rest) = input.split_at(length);
+    let header = match next_header {
+        0 => ExtensionHeader::HopByHop(parse_ipv6_options(&body[2..])),
+        43 => ExtensionHeader::Routing {
+            routing_type: read_u8(body, 2)?,
+            segments_left: read_u8(body, 3)?,
+            data: body[4..].to_vec(),
+        },
+        44 => {
+            let offset_flags = read_u16(body, 2)?;
+            ExtensionHeader::Fragment {
+                fragment_offset: offset_flags >> 3,
+                more_fragments: offset_flags & 1 == 1,
+                id: read_u32(body, 4)?,
+            }
+        }
+        60 => ExtensionHeader::DestinationOptions(parse_ipv6_options(&body[2..])),
+        _ => {
+            if length < 12 {
+                return Err(ParseError::BadHeaderLength { protocol: "IPv6 authentication", length });
+            }
+            ExtensionHeader::Authentication {
+                spi: read_u32(body, 4)?,
+                sequence_no: read_u32(body, 8)?,
+                icv: body[12..].to_vec(),
+            }
+        }
+    };
+    Ok((rest, following_header, header))
+}
+
+pub fn parse_ipv6(input: &[u8]) -> Result<(&[u8], IPv6Datagram), ParseError> {
+    ensure_length(input, 40)?;
+    let version_class_flow = read_u32(input, 0)?;
+    let version = (version_class_flow >> 28) as u8;
+    if version != 6 {
+        return Err(ParseError::InvalidValue { field: "ipv6.version", value: version as u32 });
+    }
+    let traffic_class = (version_class_flow >> 20) as u8;
+    let flow_label = version_class_flow & 0x000F_FFFF;
+    let payload_length = read_u16(input, 4)?;
+    let next_header = read_u8(input, 6)?;
+    let hop_limit = read_u8(input, 7)?;
+    let source_addr = Ipv6Addr::from(read_array::<16>(input, 8)?);
+    let dest_addr = Ipv6Addr::from(read_array::<16>(input, 24)?);
+
+    // Drop link layer padding after the payload. A zero payload length is left
+    // unbounded, as a jumbogram gives its length in a hop-by-hop option.
+    let mut input = skip(input, 40)?;
+    if payload_length != 0 && (payload_length as usize) < input.len() {
+        input = &input[..payload_length as usize];
+    }
+    let payload_start = input.len();
+    let mut extension_headers: Vec<ExtensionHeader> = vec![];
+    let mut protocol = next_header;
+    while matches!(protocol, 0 | 43 | 44 | 51 | 60) {
+        let (rest, following_header, header) = parse_extension_header(protocol, input)?;
+        let later_fragment = matches!(header, ExtensionHeader::Fragment { fragment_offset, .. } if fragment_offset != 0);
+        input = rest;
+        protocol = following_header;
+        extension_headers.push(header);
+        if later_fragment {
+            break;
+        }
+    }
+
+    let datagram = IPv6Datagram {
+        version,
+        dscp: traffic_class >> 2,
+        ecn: Ecn::from(traffic_class),
+        flow_label,
+        payload_length,
+        next_header,
+        hop_limit,
+        source_addr,
+        dest_addr,
+        extension_headers,
+        extension_length: payload_start - input.len(),
+        protocol: IPType::from(protocol),
+    };
+    Ok((input, datagram))
+}
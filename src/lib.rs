@@ -0,0 +1,30 @@
+#![allow(clippy::upper_case_acronyms, clippy::enum_variant_names)]
+
+mod bytes;
+pub mod checksum;
+pub mod error;
+pub mod ethernet;
+pub mod sll;
+pub mod loopback;
+pub mod ppp;
+pub mod ipv4;
+pub mod ipv6;
+pub mod tcp;
+pub mod udp;
+pub mod arp;
+pub mod icmp;
+pub mod icmpv6;
+pub mod packet;
+pub mod defrag;
+pub mod flow;
+pub mod stream;
+pub mod analysis;
+pub mod metrics;
+pub mod ping;
+pub mod filter;
+pub mod stats;
+#[cfg(test)]
+mod testing;
+
+pub use error::ParseError;
+pub use packet::{dissect, LinkType, Packet};
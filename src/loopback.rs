@@ -0,0 +1,63 @@
+use crate::bytes::{ensure_length, read_array};
+use crate::error::ParseError;
+use crate::ethernet::EtherType;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressFamily {
+    IPv4,
+    IPv6,
+    Other(u32),
+}
+
+impl From<u32> for AddressFamily {
+    fn from(raw: u32) -> Self {
+        match raw {
+            2 => Self::IPv4,
+            // Linux, the BSDs and Darwin all picked different values for AF_INET6.
+            10 | 24 | 28 | 30 => Self::IPv6,
+            other => Self::Other(other),
+        }
+    }
+}
+
+impl From<AddressFamily> for EtherType {
+    fn from(family: AddressFamily) -> Self {
+        match family {
+            AddressFamily::IPv4 => EtherType::IPv4,
+            AddressFamily::IPv6 => EtherType::IPv6,
+            AddressFamily::Other(_) => EtherType::Other(0),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LoopbackHeader {
+    pub family: AddressFamily,
+}
+
+// DLT_NULL stores the family in the byte order of the capturing host, which may
+// not be ours; a valid family never uses the upper two bytes, so pick the order
+// that leaves them empty.
+pub fn parse_null(input: &[u8]) -> Result<(&[u8], LoopbackHeader), ParseError> {
+    ensure_length(input, 4)?;
+    let raw = read_array::<4>(input, 0)?;
+    let family = match u32::from_le_bytes(raw) {
+        family if family & 0xFFFF_0000 == 0 => family,
+        _ => u32::from_be_bytes(raw),
+    };
+
+    let (_, input) = input.split_at(4);
+
+    let header = LoopbackHeader { family: AddressFamily::from(family) };
+    Ok((input, header))
+}
+
+pub fn parse_loop(input: &[u8]) -> Result<(&[u8], LoopbackHeader), ParseError> {
+    ensure_length(input, 4)?;
+    let family = u32::from_be_bytes(read_array(input, 0)?);
+
+    let (_, input) = input.split_at(4);
+
+    let header = LoopbackHeader { family: AddressFamily::from(family) };
+    Ok((input, header))
+}
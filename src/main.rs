@@ -1,78 +1,307 @@
-mod ethernet;
-mod ipv4;
-mod tcp;
-mod udp;
-mod arp;
-mod icmp;
-use pcap::Capture;
-use std::error::Error;
+mod cli;
+mod follow;
 
+use cli::{Command, Options, Source};
+use follow::Follower;
+use mohole::analysis::{AnalysisConfig, TcpAnalysisFlag, TcpAnalyzer};
+use mohole::checksum::ChecksumStatus;
+use mohole::defrag::{DefragConfig, DefragResult, Defragmenter};
+use mohole::flow::{Connection, FlowConfig, FlowTracker};
+use mohole::metrics::{self, ConnectionMetrics, MetricsConfig, MetricsTracker, Sample};
+use mohole::packet::{dissect_reassembled, LinkLayer, NetworkLayer, TransportLayer};
+use mohole::ping::{EchoResult, PingConfig, PingTracker};
+use mohole::stats::CaptureStats;
+use mohole::udp::UdpLengthStatus;
+use mohole::{dissect, LinkType, Packet};
+use pcap::{Activated, Capture, Device, PacketHeader};
+use std::env;
+use std::fs::File;
+use std::io::BufWriter;
+use std::process::ExitCode;
+use std::time::Duration;
 
-fn main() -> Result<(), Box<dyn Error>> {
-    // let mut cap = Capture::from_device("wlp5s0").unwrap().open().unwrap();
-    let mut cap = Capture::from_file("aol-packets.pcap").unwrap();
-    while let Ok(packet) = cap.next() {
 
-        println!();
-        if let Ok((payload, frame)) = ethernet::parse_ethernet(packet.data) {
-            println!("{:x?}", frame);
-            match frame.ethertype {
-                ethernet::EtherType::IPv4 => {
-                    if let Ok((payload, datagram)) = ipv4::parse_ipv4(payload) {
-                        println!("{:?}", datagram);
-                        match datagram.protocol {
-                            ipv4::IPType::TCP => {
-                                if let Ok((_payload, segment)) = tcp::parse_tcp(payload) {
-                                    println!("{:?}", segment);
-                                    if segment.dest_port == 80 || segment.source_port == 80 {
-                                        println!("HTTP message.");
-                                    } else if segment.dest_port == 443 || segment.source_port == 443 {
-                                        println!("HTTPS message.");
-                                    } else if segment.dest_port == 22 || segment.source_port == 22 {
-                                        println!("SSH message.");
-                                    }
-                                } else {
-                                    println!("Error parsing TCP segment.");
-                                }
-                            },
-                            ipv4::IPType::UDP => {
-                                if let Ok((_payload, udp_datagram)) = udp::parse_udp(payload) {
-                                    println!("{:?}", udp_datagram);
-                                    if udp_datagram.dest_port == 123 || udp_datagram.source_port == 123 {
-                                        println!("NTP message.");
-                                    } else if udp_datagram.dest_port == 443 || udp_datagram.source_port == 443 {
-                                        println!("QUIC message.");
-                                    }
-                                } else {
-                                        println!("Error parsing UDP datagram.");
-                                    }
-                            },
-                            ipv4::IPType::ICMP => {
-                                if let Ok((_payload, packet)) = icmp::parse_icmp(payload) {
-                                    println!("{:?}", packet);
-                                } else {
-                                        println!("Error parsing ICMP packet.");
-                                    }
-                            },
-                            _ => { println!("L4 protocol not supported")}
+fn main() -> ExitCode {
+    let command = match cli::parse_args(env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("mohole: {}", e);
+            eprintln!("{}", cli::USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    let result = match command {
+        Command::Help => {
+            println!("{}", cli::USAGE);
+            Ok(())
+        }
+        Command::ListInterfaces => list_interfaces(),
+        Command::Capture(options) => capture(&options),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("mohole: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn list_interfaces() -> Result<(), String> {
+    let devices = Device::list().map_err(|e| format!("cannot list interfaces: {}", e))?;
+    for (index, device) in devices.iter().enumerate() {
+        match &device.desc {
+            Some(desc) => println!("{}.{} ({})", index + 1, device.name, desc),
+            None => println!("{}.{}", index + 1, device.name),
+        }
+    }
+    Ok(())
+}
+
+fn capture(options: &Options) -> Result<(), String> {
+    match &options.source {
+        Source::File(path) => {
+            let mut cap = Capture::from_file(path)
+                .map_err(|e| format!("cannot open capture file {}: {}", path, e))?;
+            read_packets(&mut cap, options)
+        }
+        Source::Interface(name) => {
+            let mut cap = open_device(Device::from(name.as_str()), options)?;
+            read_packets(&mut cap, options)
+        }
+        Source::DefaultInterface => {
+            let device = Device::lookup().map_err(|e| format!("cannot find a default interface: {}", e))?;
+            let mut cap = open_device(device, options)?;
+            read_packets(&mut cap, options)
+        }
+    }
+}
+
+fn open_device(device: Device, options: &Options) -> Result<Capture<pcap::Active>, String> {
+    let name = device.name.clone();
+    Capture::from_device(device)
+        .map(|cap| cap.promisc(options.promiscuous).snaplen(options.snaplen))
+        .and_then(|cap| cap.open())
+        .map_err(|e| format!("cannot capture on interface {}: {}", name, e))
+}
+
+fn read_packets<T: Activated + ?Sized>(cap: &mut Capture<T>, options: &Options) -> Result<(), String> {
+    if let Some(expression) = &options.capture_filter {
+        cap.filter(expression, true)
+            .map_err(|e| format!("invalid capture filter '{}': {}", expression, e))?;
+    }
+    let linktype = LinkType::from(cap.get_datalink().0);
+    let mut seen = 0;
+    let mut stats = CaptureStats::default();
+    let mut defragmenter = Defragmenter::new(DefragConfig::default());
+    let mut flows = options.track_connections.then(|| FlowTracker::new(FlowConfig::default()));
+    let mut follower = options.follow.clone().map(Follower::new);
+    let mut analyzer = TcpAnalyzer::new(AnalysisConfig::default());
+    let mut metrics = options
+        .metrics
+        .is_some()
+        .then(|| MetricsTracker::new(MetricsConfig { keep_samples: true, ..MetricsConfig::default() }));
+    let mut measured: Vec<ConnectionMetrics> = vec![];
+    let mut pings = PingTracker::new(PingConfig::default());
+    while options.count.is_none_or(|count| seen < count) {
+        match cap.next() {
+            Ok(packet) => {
+                seen += 1;
+                let now = timestamp(packet.header);
+                let parsed = dissect(linktype, packet.data);
+                let defragmented = parsed.ipv4().map(|datagram| defragmenter.process(now, datagram, parsed.payload));
+                if let Some(follower) = follower.as_mut() {
+                    follower.process(now, &parsed);
+                    if let Some(DefragResult::Complete(reassembled)) = &defragmented {
+                        follower.process(now, &dissect_reassembled(reassembled));
+                    }
+                    continue;
+                }
+                stats.record(&parsed);
+                analyzer.expire(now);
+                let analysis = analyzer.analyze(now, &parsed);
+                pings.expire(now);
+                let echo = pings.process(now, &parsed);
+                let shown = show_packet(&parsed, &analysis, echo, options);
+                if let Some(flows) = flows.as_mut() {
+                    print_connections(flows.process(now, &parsed));
+                }
+                if let Some(metrics) = metrics.as_mut() {
+                    measured.extend(metrics.process(now, &parsed));
+                }
+                match defragmented {
+                    Some(DefragResult::Complete(reassembled)) => {
+                        let whole = dissect_reassembled(&reassembled);
+                        stats.record_reassembled(&whole);
+                        let analysis = analyzer.analyze(now, &whole);
+                        let echo = pings.process(now, &whole);
+                        if let Some(flows) = flows.as_mut() {
+                            print_connections(flows.process(now, &whole));
+                        }
+                        if let Some(metrics) = metrics.as_mut() {
+                            measured.extend(metrics.process(now, &whole));
+                        }
+                        if options.display_filter.as_ref().is_none_or(|filter| filter.matches(&whole)) {
+                            println!();
+                            println!("Reassembled IPv4 datagram from {} fragments.", reassembled.fragments);
+                            if !reassembled.anomalies.is_empty() {
+                                println!("!!! FRAGMENT ANOMALIES: {:?} !!!", reassembled.anomalies);
+                            }
+                            print_packet(&whole, &analysis, echo);
                         }
-                    } else {
-                        println!("Error parsing IP datagram.");
                     }
-                },
-                ethernet::EtherType::ARP => {
-                    if let Ok((_payload, packet)) = arp::parse_arp(payload) {
-                        println!("{:x?}", packet);
-                    } else {
-                        println!("Error parsing ARP packet.");
+                    Some(DefragResult::Dropped(anomaly)) if shown => {
+                        println!("!!! FRAGMENTED DATAGRAM DROPPED: {:?} !!!", anomaly);
                     }
+                    _ => {}
                 }
-                _ => { println!("L3 protocol not supported")}
             }
-        } else {
-            println!("Error parsing Ethernet frame.");
+            Err(pcap::Error::TimeoutExpired) => continue,
+            Err(pcap::Error::NoMorePackets) => break,
+            Err(e) => return Err(format!("error while capturing packets: {}", e)),
         }
-
     }
+    if let Some(follower) = follower {
+        return follower.finish();
+    }
+    if let Some(flows) = flows.as_mut() {
+        print_connections(flows.flush());
+    }
+    println!();
+    println!("{}", stats);
+    println!("TCP analysis: {}", analyzer.summary());
+    pings.flush();
+    for summary in pings.summaries() {
+        println!("Ping {}", summary);
+    }
+    if let (Some(metrics), Some(prefix)) = (metrics.as_mut(), &options.metrics) {
+        measured.extend(metrics.flush());
+        measured.sort_by_key(|connection| connection.index);
+        write_metrics(&measured, metrics.samples(), prefix)?;
+    }
+    Ok(())
+}
+
+fn write_metrics(connections: &[ConnectionMetrics], samples: &[Sample], prefix: &str) -> Result<(), String> {
+    let path = format!("{}.connections.csv", prefix);
+    File::create(&path)
+        .and_then(|file| metrics::write_connections_csv(connections, BufWriter::new(file)))
+        .map_err(|e| format!("cannot write {}: {}", path, e))?;
+    println!("Wrote metrics for {} TCP connections to {}", connections.len(), path);
+    let path = format!("{}.samples.csv", prefix);
+    File::create(&path)
+        .and_then(|file| metrics::write_samples_csv(samples, BufWriter::new(file)))
+        .map_err(|e| format!("cannot write {}: {}", path, e))?;
+    println!("Wrote {} samples to {}", samples.len(), path);
     Ok(())
 }
+
+fn print_connections(connections: Vec<Connection>) {
+    for connection in connections {
+        println!();
+        println!("{}", connection);
+    }
+}
+
+fn timestamp(header: &PacketHeader) -> Duration {
+    Duration::new(header.ts.tv_sec as u64, header.ts.tv_usec as u32 * 1000)
+}
+
+fn show_packet(packet: &Packet, analysis: &[TcpAnalysisFlag], echo: Option<EchoResult>, options: &Options) -> bool {
+    if options.display_filter.as_ref().is_some_and(|filter| !filter.matches(packet)) {
+        return false;
+    }
+    println!();
+    print_packet(packet, analysis, echo);
+    true
+}
+
+fn print_packet(packet: &Packet, analysis: &[TcpAnalysisFlag], echo: Option<EchoResult>) {
+    match &packet.link {
+        Some(LinkLayer::Ethernet(frame)) => println!("{:x?}", frame),
+        Some(LinkLayer::LinuxSLL(header)) => println!("{:x?}", header),
+        Some(LinkLayer::LinuxSLL2(header)) => println!("{:x?}", header),
+        Some(LinkLayer::Loopback(header)) => println!("{:?}", header),
+        Some(LinkLayer::PPP(header)) => println!("{:x?}", header),
+        Some(LinkLayer::Raw) => {}
+        None => {
+            if packet.network.is_none() && packet.error.is_none() {
+                println!("L2 protocol not supported");
+            }
+        }
+    }
+    match &packet.network {
+        Some(NetworkLayer::IPv4(datagram)) => {
+            println!("{:?}", datagram);
+            if let ChecksumStatus::Bad { expected } = datagram.checksum_status {
+                println!(
+                    "!!! BAD IPv4 HEADER CHECKSUM {:#06x} (expected {:#06x}) !!!",
+                    datagram.header_checksum, expected
+                );
+            }
+        }
+        Some(NetworkLayer::IPv6(datagram)) => println!("{:?}", datagram),
+        Some(NetworkLayer::ARP(arp_packet)) => println!("{:x?}", arp_packet),
+        None => {
+            if packet.link.is_some() && packet.error.is_none() {
+                println!("L3 protocol not supported");
+            }
+        }
+    }
+    match &packet.transport {
+        Some(TransportLayer::TCP(segment)) => {
+            println!("{:?}", segment);
+            if let ChecksumStatus::Bad { expected } = segment.checksum_status {
+                println!("!!! BAD TCP CHECKSUM {:#06x} (expected {:#06x}) !!!", segment.checksum, expected);
+            }
+            if !analysis.is_empty() {
+                let flags: Vec<String> = analysis.iter().map(|flag| flag.to_string()).collect();
+                println!("[{}]", flags.join(", "));
+            }
+            if segment.dest_port == 80 || segment.source_port == 80 {
+                println!("HTTP message.");
+            } else if segment.dest_port == 443 || segment.source_port == 443 {
+                println!("HTTPS message.");
+            } else if segment.dest_port == 22 || segment.source_port == 22 {
+                println!("SSH message.");
+            }
+        },
+        Some(TransportLayer::UDP(udp_datagram)) => {
+            println!("{:?}", udp_datagram);
+            if let ChecksumStatus::Bad { expected } = udp_datagram.checksum_status {
+                println!("!!! BAD UDP CHECKSUM {:#06x} (expected {:#06x}) !!!", udp_datagram.checksum, expected);
+            }
+            match udp_datagram.length_status {
+                UdpLengthStatus::Good => {}
+                UdpLengthStatus::TooShort => println!("!!! BAD UDP LENGTH {} (shorter than the header) !!!", udp_datagram.length),
+                UdpLengthStatus::Truncated { captured } => {
+                    println!("!!! UDP DATAGRAM TRUNCATED: length {} but only {} bytes captured !!!", udp_datagram.length, captured)
+                }
+                UdpLengthStatus::IpMismatch { ip_length } => {
+                    println!("!!! BAD UDP LENGTH {} (IP payload is {} bytes) !!!", udp_datagram.length, ip_length)
+                }
+            }
+            if udp_datagram.padding > 0 {
+                println!("UDP datagram followed by {} bytes of padding.", udp_datagram.padding);
+            }
+            if udp_datagram.dest_port == 123 || udp_datagram.source_port == 123 {
+                println!("NTP message.");
+            } else if udp_datagram.dest_port == 443 || udp_datagram.source_port == 443 {
+                println!("QUIC message.");
+            }
+        },
+        Some(TransportLayer::ICMP(icmp_packet)) => println!("{:?}", icmp_packet),
+        Some(TransportLayer::ICMPv6(icmp_packet)) => println!("{:x?}", icmp_packet),
+        None => {
+            if (packet.ipv4().is_some() || packet.ipv6().is_some()) && packet.error.is_none() {
+                println!("L4 protocol not supported");
+            }
+        }
+    }
+    if let Some(echo) = echo.filter(|echo| *echo != EchoResult::Request) {
+        println!("[{}]", echo);
+    }
+    if let Some(e) = &packet.error {
+        println!("Error parsing {} header: {}", e.protocol, e.error);
+    }
+}
@@ -0,0 +1,551 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Write};
+use std::time::Duration;
+use crate::flow::{Direction, Endpoint, FlowKey};
+use crate::packet::Packet;
+use crate::tcp::{seq_after, seq_before, TcpFlags, TcpOption};
+
+// Bound on the segments and timestamp values remembered per direction while
+// waiting for them to be acknowledged.
+const MAX_OUTSTANDING: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetricsConfig {
+    pub idle_timeout: Duration,
+    // How long a closed connection is kept around to measure its last ACKs.
+    pub close_timeout: Duration,
+    // Samples are only kept, for export, when this is set.
+    pub keep_samples: bool,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            idle_timeout: Duration::from_secs(300),
+            close_timeout: Duration::from_secs(10),
+            keep_samples: false,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RttStats {
+    pub samples: u64,
+    pub min: Option<Duration>,
+    pub max: Option<Duration>,
+    pub total: Duration,
+    // Sum of the differences between consecutive samples.
+    pub variation: Duration,
+    last: Option<Duration>,
+}
+
+impl RttStats {
+    pub(crate) fn record(&mut self, rtt: Duration) {
+        self.samples += 1;
+        self.min = Some(self.min.map_or(rtt, |min| min.min(rtt)));
+        self.max = Some(self.max.map_or(rtt, |max| max.max(rtt)));
+        self.total += rtt;
+        if let Some(last) = self.last {
+            self.variation += rtt.abs_diff(last);
+        }
+        self.last = Some(rtt);
+    }
+
+    // The sample count can exceed u32::MAX, which `Duration` division takes.
+    pub fn average(&self) -> Option<Duration> {
+        (self.samples > 0).then(|| Duration::from_secs_f64(self.total.as_secs_f64() / self.samples as f64))
+    }
+
+    // Mean difference between consecutive samples.
+    pub fn jitter(&self) -> Option<Duration> {
+        (self.samples > 1).then(|| Duration::from_secs_f64(self.variation.as_secs_f64() / (self.samples - 1) as f64))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    SeqAckRtt(Duration),
+    TimestampRtt(Duration),
+    BytesInFlight(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    pub time: Duration,
+    pub connection: usize,
+    pub direction: Direction,
+    pub metric: Metric,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct DirectionMetrics {
+    pub seq_ack_rtt: RttStats,
+    pub timestamp_rtt: RttStats,
+    // Payload bytes sent for the first time, leaving out retransmissions.
+    pub unique_bytes: u64,
+    pub first_data: Option<Duration>,
+    pub last_data: Option<Duration>,
+    pub max_bytes_in_flight: u32,
+    next_seq: Option<u32>,
+    last_ack: Option<u32>,
+    // End sequence numbers of segments waiting for an ACK. Retransmitted
+    // segments are dropped so they never produce a sample (Karn's algorithm).
+    unacked: VecDeque<(u32, Duration)>,
+    // Timestamp values sent and not yet echoed back.
+    timestamps: VecDeque<(u32, Duration)>,
+    fin_seen: bool,
+}
+
+impl DirectionMetrics {
+    // Unique payload bytes per second between the first and the last data segment.
+    pub fn goodput(&self) -> Option<f64> {
+        let elapsed = self.last_data?.saturating_sub(self.first_data?).as_secs_f64();
+        (elapsed > 0.0).then(|| self.unique_bytes as f64 / elapsed)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectionMetrics {
+    // Connections are numbered from 0 in the order they first appear.
+    pub index: usize,
+    pub client: Endpoint,
+    pub server: Endpoint,
+    pub first_seen: Duration,
+    pub last_seen: Duration,
+    pub syn_time: Option<Duration>,
+    pub syn_ack_time: Option<Duration>,
+    pub ack_time: Option<Duration>,
+    // Set by a RST, or by the second FIN.
+    pub closed_time: Option<Duration>,
+    pub client_to_server: DirectionMetrics,
+    pub server_to_client: DirectionMetrics,
+}
+
+impl ConnectionMetrics {
+    // Time from the client's SYN to its ACK of the SYN-ACK, which is the round
+    // trip as seen from wherever the capture was taken.
+    pub fn handshake_rtt(&self) -> Option<Duration> {
+        Some(self.ack_time?.saturating_sub(self.syn_time?))
+    }
+
+    fn sides(&mut self, direction: Direction) -> (&mut DirectionMetrics, &mut DirectionMetrics) {
+        match direction {
+            Direction::ClientToServer => (&mut self.client_to_server, &mut self.server_to_client),
+            Direction::ServerToClient => (&mut self.server_to_client, &mut self.client_to_server),
+        }
+    }
+
+    // Drops what was kept for matching ACKs, which a finished connection no longer needs.
+    fn finish(mut self) -> ConnectionMetrics {
+        for side in [&mut self.client_to_server, &mut self.server_to_client] {
+            side.unacked = VecDeque::new();
+            side.timestamps = VecDeque::new();
+        }
+        self
+    }
+}
+
+#[derive(Debug)]
+pub struct MetricsTracker {
+    config: MetricsConfig,
+    connections: HashMap<FlowKey, ConnectionMetrics>,
+    next_index: usize,
+    samples: Vec<Sample>,
+}
+
+impl MetricsTracker {
+    pub fn new(config: MetricsConfig) -> Self {
+        MetricsTracker { config, connections: HashMap::new(), next_index: 0, samples: vec![] }
+    }
+
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    pub fn active(&self) -> usize {
+        self.connections.len()
+    }
+
+    fn drain<F: Fn(&ConnectionMetrics) -> bool>(&mut self, select: F) -> Vec<ConnectionMetrics> {
+        let keys: Vec<FlowKey> = self
+            .connections
+            .iter()
+            .filter(|(_, connection)| select(connection))
+            .map(|(key, _)| *key)
+            .collect();
+        let mut finished: Vec<ConnectionMetrics> = keys
+            .into_iter()
+            .filter_map(|key| self.connections.remove(&key).map(ConnectionMetrics::finish))
+            .collect();
+        finished.sort_by_key(|connection| connection.index);
+        finished
+    }
+
+    pub fn expire(&mut self, now: Duration) -> Vec<ConnectionMetrics> {
+        let config = self.config;
+        self.drain(|connection| match connection.closed_time {
+            Some(closed) => now.saturating_sub(closed) > config.close_timeout,
+            None => now.saturating_sub(connection.last_seen) > config.idle_timeout,
+        })
+    }
+
+    pub fn flush(&mut self) -> Vec<ConnectionMetrics> {
+        self.drain(|_| true)
+    }
+
+    // Feeds one packet to the tracker and returns the connections that ended,
+    // including the ones that timed out before it.
+    pub fn process(&mut self, now: Duration, packet: &Packet) -> Vec<ConnectionMetrics> {
+        let mut finished = self.expire(now);
+        let (segment, (source_addr, dest_addr)) = match (packet.tcp(), packet.ip_addresses()) {
+            (Some(segment), Some(addresses)) => (segment, addresses),
+            _ => return finished,
+        };
+        let source = Endpoint { addr: source_addr, port: segment.source_port };
+        let dest = Endpoint { addr: dest_addr, port: segment.dest_port };
+        let key = FlowKey::new(source, dest);
+        let flags = segment.flags;
+        let new_syn = flags.contains(TcpFlags::SYN) && !flags.contains(TcpFlags::ACK);
+
+        // A fresh SYN after the handshake or the close means the 4-tuple is being reused.
+        let reused = self.connections.get(&key).is_some_and(|connection| {
+            connection.closed_time.is_some() || connection.ack_time.is_some()
+        });
+        if new_syn && reused {
+            if let Some(connection) = self.connections.remove(&key) {
+                finished.push(connection.finish());
+            }
+        }
+        let connection = match self.connections.entry(key) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(_) if flags.contains(TcpFlags::RST) => return finished,
+            Entry::Vacant(entry) => {
+                let (client, server) = if flags.contains(TcpFlags::SYN) {
+                    if flags.contains(TcpFlags::ACK) { (dest, source) } else { (source, dest) }
+                } else if source.port >= dest.port {
+                    (source, dest)
+                } else {
+                    (dest, source)
+                };
+                self.next_index += 1;
+                entry.insert(ConnectionMetrics {
+                    index: self.next_index - 1,
+                    client,
+                    server,
+                    first_seen: now,
+                    last_seen: now,
+                    syn_time: None,
+                    syn_ack_time: None,
+                    ack_time: None,
+                    closed_time: None,
+                    client_to_server: DirectionMetrics::default(),
+                    server_to_client: DirectionMetrics::default(),
+                })
+            }
+        };
+        connection.last_seen = now;
+        let direction = if source == connection.client { Direction::ClientToServer } else { Direction::ServerToClient };
+        match direction {
+            Direction::ClientToServer if flags.contains(TcpFlags::SYN) && !flags.contains(TcpFlags::ACK) => {
+                connection.syn_time.get_or_insert(now);
+            }
+            Direction::ServerToClient if flags.contains(TcpFlags::SYN | TcpFlags::ACK) => {
+                connection.syn_ack_time.get_or_insert(now);
+            }
+            Direction::ClientToServer if connection.syn_ack_time.is_some() && flags.contains(TcpFlags::ACK) => {
+                connection.ack_time.get_or_insert(now);
+            }
+            _ => {}
+        }
+
+        let connection_index = connection.index;
+        let mut samples: Vec<Sample> = vec![];
+        let mut sample = |metric: Metric, direction: Direction| {
+            samples.push(Sample { time: now, connection: connection_index, direction, metric });
+        };
+        let reverse_direction = match direction {
+            Direction::ClientToServer => Direction::ServerToClient,
+            Direction::ServerToClient => Direction::ClientToServer,
+        };
+        let (sender, receiver) = connection.sides(direction);
+        let timestamp = segment.options.iter().flatten().find_map(|option| match option {
+            TcpOption::Timestamp(value, echo) => Some((*value, *echo)),
+            _ => None,
+        });
+
+        // ACKs and timestamp echoes measure the round trip of the other side's data.
+        if flags.contains(TcpFlags::ACK) {
+            let ack = segment.ack_no;
+            let mut newest = None;
+            while let Some(&(end, sent)) = receiver.unacked.front() {
+                if seq_after(end, ack) {
+                    break;
+                }
+                newest = Some(sent);
+                receiver.unacked.pop_front();
+            }
+            if let Some(sent) = newest {
+                let rtt = now.saturating_sub(sent);
+                receiver.seq_ack_rtt.record(rtt);
+                sample(Metric::SeqAckRtt(rtt), reverse_direction);
+            }
+            sender.last_ack = Some(ack);
+        }
+        if let Some((_, echo)) = timestamp.filter(|(_, echo)| *echo != 0 && flags.contains(TcpFlags::ACK)) {
+            if let Some(position) = receiver.timestamps.iter().position(|(value, _)| *value == echo) {
+                let rtt = now.saturating_sub(receiver.timestamps[position].1);
+                receiver.timestamps.drain(..=position);
+                receiver.timestamp_rtt.record(rtt);
+                sample(Metric::TimestampRtt(rtt), reverse_direction);
+            }
+        }
+
+        let syn = flags.contains(TcpFlags::SYN) as u32;
+        let payload_length = packet.payload.len() as u32;
+        let length = segment.sequence_length(packet.payload.len());
+        if length > 0 {
+            let seq = segment.sequence_no;
+            let end = seq.wrapping_add(length);
+            let retransmission = sender.next_seq.is_some_and(|next_seq| seq_before(seq, next_seq));
+            if retransmission {
+                sender.unacked.retain(|(unacked_end, _)| !seq_after(*unacked_end, seq));
+            } else {
+                sender.unacked.push_back((end, now));
+                if sender.unacked.len() > MAX_OUTSTANDING {
+                    sender.unacked.pop_front();
+                }
+            }
+            if let Some((value, _)) = timestamp {
+                if !retransmission && sender.timestamps.back().is_none_or(|(last, _)| *last != value) {
+                    sender.timestamps.push_back((value, now));
+                    if sender.timestamps.len() > MAX_OUTSTANDING {
+                        sender.timestamps.pop_front();
+                    }
+                }
+            }
+            if sender.next_seq.is_none_or(|next_seq| seq_after(end, next_seq)) {
+                let payload_start = seq.wrapping_add(syn);
+                let new_start = match sender.next_seq {
+                    Some(next_seq) if seq_after(next_seq, payload_start) => next_seq,
+                    _ => payload_start,
+                };
+                let new_bytes = payload_start.wrapping_add(payload_length).wrapping_sub(new_start);
+                if payload_length > 0 && new_bytes <= payload_length {
+                    sender.unique_bytes += new_bytes as u64;
+                    sender.first_data.get_or_insert(now);
+                    sender.last_data = Some(now);
+                }
+                sender.next_seq = Some(end);
+            }
+            if let (Some(next_seq), Some(ack)) = (sender.next_seq, receiver.last_ack) {
+                let in_flight = next_seq.wrapping_sub(ack);
+                if (in_flight as i32) >= 0 {
+                    sender.max_bytes_in_flight = sender.max_bytes_in_flight.max(in_flight);
+                    sample(Metric::BytesInFlight(in_flight), direction);
+                }
+            }
+        }
+        if flags.contains(TcpFlags::FIN) {
+            sender.fin_seen = true;
+        }
+        if flags.contains(TcpFlags::RST) || (sender.fin_seen && receiver.fin_seen) {
+            connection.closed_time.get_or_insert(now);
+        }
+        if self.config.keep_samples {
+            self.samples.extend(samples);
+        }
+        finished
+    }
+}
+
+fn direction_name(direction: Direction) -> &'static str {
+    match direction {
+        Direction::ClientToServer => "client_to_server",
+        Direction::ServerToClient => "server_to_client",
+    }
+}
+
+fn millis(duration: Option<Duration>) -> String {
+    duration.map_or(String::new(), |duration| format!("{:.3}", duration.as_secs_f64() * 1000.0))
+}
+
+pub fn write_samples_csv<W: Write>(samples: &[Sample], mut writer: W) -> io::Result<()> {
+    writeln!(writer, "connection,time,direction,metric,value")?;
+    for sample in samples {
+        let (metric, value) = match sample.metric {
+            Metric::SeqAckRtt(rtt) => ("rtt_ms", millis(Some(rtt))),
+            Metric::TimestampRtt(rtt) => ("timestamp_rtt_ms", millis(Some(rtt))),
+            Metric::BytesInFlight(bytes) => ("bytes_in_flight", bytes.to_string()),
+        };
+        writeln!(
+            writer,
+            "{},{:.6},{},{},{}",
+            sample.connection,
+            sample.time.as_secs_f64(),
+            direction_name(sample.direction),
+            metric,
+            value
+        )?;
+    }
+    Ok(())
+}
+
+pub fn write_connections_csv<W: Write>(connections: &[ConnectionMetrics], mut writer: W) -> io::Result<()> {
+    write!(writer, "connection,client,server,first_seen,last_seen,handshake_rtt_ms")?;
+    for direction in ["client_to_server", "server_to_client"] {
+        for column in [
+            "bytes",
+            "goodput_bps",
+            "rtt_samples",
+            "rtt_min_ms",
+            "rtt_avg_ms",
+            "rtt_max_ms",
+            "rtt_jitter_ms",
+            "timestamp_rtt_samples",
+            "timestamp_rtt_avg_ms",
+            "max_bytes_in_flight",
+        ] {
+            write!(writer, ",{}_{}", direction, column)?;
+        }
+    }
+    writeln!(writer)?;
+    for connection in connections {
+        write!(
+            writer,
+            "{},{},{},{:.6},{:.6},{}",
+            connection.index,
+            connection.client,
+            connection.server,
+            connection.first_seen.as_secs_f64(),
+            connection.last_seen.as_secs_f64(),
+            millis(connection.handshake_rtt())
+        )?;
+        for metrics in [&connection.client_to_server, &connection.server_to_client] {
+            write!(
+                writer,
+                ",{},{},{},{},{},{},{},{},{},{}",
+                metrics.unique_bytes,
+                metrics.goodput().map_or(String::new(), |goodput| format!("{:.0}", goodput * 8.0)),
+                metrics.seq_ack_rtt.samples,
+                millis(metrics.seq_ack_rtt.min),
+                millis(metrics.seq_ack_rtt.average()),
+                millis(metrics.seq_ack_rtt.max),
+                millis(metrics.seq_ack_rtt.jitter()),
+                metrics.timestamp_rtt.samples,
+                millis(metrics.timestamp_rtt.average()),
+                metrics.max_bytes_in_flight
+            )?;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{dissect, LinkType};
+    use crate::testing::tcp;
+
+    const ACK: TcpFlags = TcpFlags::ACK;
+
+    fn send(tracker: &mut MetricsTracker, millis: u64, from_client: bool, seq: u32, ack: u32, flags: TcpFlags, payload: &[u8]) -> Vec<ConnectionMetrics> {
+        let bytes = tcp(from_client, seq, ack, flags, payload);
+        tracker.process(Duration::from_millis(millis), &dissect(LinkType::IPv4, &bytes))
+    }
+
+    // Handshake and one request acknowledged 10ms later, starting at `start` ms.
+    fn exchange(tracker: &mut MetricsTracker, start: u64) {
+        assert!(send(tracker, start, true, 0, 0, TcpFlags::SYN, b"").is_empty());
+        send(tracker, start + 10, false, 5000, 1, TcpFlags::SYN | ACK, b"");
+        send(tracker, start + 20, true, 1, 5001, ACK, b"");
+        send(tracker, start + 30, true, 1, 5001, ACK, b"GET /");
+        send(tracker, start + 40, false, 5001, 6, ACK, b"");
+    }
+
+    #[test]
+    fn measures_handshake_and_data_rtt() {
+        let mut tracker = MetricsTracker::new(MetricsConfig { keep_samples: true, ..MetricsConfig::default() });
+        exchange(&mut tracker, 0);
+        let connections = tracker.flush();
+        assert_eq!(connections.len(), 1);
+        let connection = &connections[0];
+        assert_eq!(connection.handshake_rtt(), Some(Duration::from_millis(20)));
+        assert_eq!(connection.client_to_server.unique_bytes, 5);
+        assert_eq!(connection.client_to_server.seq_ack_rtt.samples, 2);
+        assert_eq!(connection.client_to_server.seq_ack_rtt.max, Some(Duration::from_millis(10)));
+        assert!(tracker.samples().contains(&Sample {
+            time: Duration::from_millis(40),
+            connection: 0,
+            direction: Direction::ClientToServer,
+            metric: Metric::SeqAckRtt(Duration::from_millis(10)),
+        }));
+    }
+
+    #[test]
+    fn closed_connections_are_kept_for_the_close_timeout() {
+        let mut tracker = MetricsTracker::new(MetricsConfig::default());
+        exchange(&mut tracker, 0);
+        send(&mut tracker, 50, true, 6, 5001, TcpFlags::FIN | ACK, b"");
+        send(&mut tracker, 60, false, 5001, 7, TcpFlags::FIN | ACK, b"");
+        assert!(send(&mut tracker, 70, true, 7, 5002, ACK, b"").is_empty());
+        assert_eq!(tracker.active(), 1);
+        assert!(tracker.expire(Duration::from_millis(10_060)).is_empty());
+        let finished = tracker.expire(Duration::from_millis(10_061));
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].closed_time, Some(Duration::from_millis(60)));
+        assert_eq!(finished[0].server_to_client.seq_ack_rtt.samples, 2);
+        assert_eq!(tracker.active(), 0);
+    }
+
+    #[test]
+    fn reset_and_idle_connections_are_expired() {
+        let mut tracker = MetricsTracker::new(MetricsConfig::default());
+        exchange(&mut tracker, 0);
+        send(&mut tracker, 50, false, 5001, 6, TcpFlags::RST, b"");
+        assert_eq!(tracker.expire(Duration::from_secs(11)).len(), 1);
+
+        exchange(&mut tracker, 20_000);
+        assert!(tracker.expire(Duration::from_secs(320)).is_empty());
+        let finished = tracker.expire(Duration::from_secs(321));
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].index, 1);
+        assert_eq!(finished[0].closed_time, None);
+    }
+
+    #[test]
+    fn new_syn_starts_a_new_connection() {
+        let mut tracker = MetricsTracker::new(MetricsConfig::default());
+        // A retransmitted SYN during the handshake belongs to the same connection.
+        send(&mut tracker, 0, true, 0, 0, TcpFlags::SYN, b"");
+        exchange(&mut tracker, 1000);
+        assert_eq!(tracker.active(), 1);
+        // The previous connection was never closed, but its handshake is done.
+        let bytes = tcp(true, 90000, 0, TcpFlags::SYN, b"");
+        let finished = tracker.process(Duration::from_millis(2000), &dissect(LinkType::IPv4, &bytes));
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].index, 0);
+        assert_eq!(finished[0].syn_time, Some(Duration::ZERO));
+        assert_eq!(finished[0].client_to_server.unique_bytes, 5);
+        let current = tracker.flush();
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0].index, 1);
+        assert_eq!(current[0].syn_time, Some(Duration::from_millis(2000)));
+        assert_eq!(current[0].client_to_server.unique_bytes, 0);
+    }
+
+    #[test]
+    fn rtt_average_and_jitter() {
+        let mut stats = RttStats::default();
+        for millis in [10, 20, 15] {
+            stats.record(Duration::from_millis(millis));
+        }
+        assert_eq!(stats.average(), Some(Duration::from_millis(15)));
+        assert_eq!(stats.jitter(), Some(Duration::from_micros(7500)));
+
+        // More samples than fit in a u32.
+        let stats = RttStats { samples: 1 << 33, total: Duration::from_secs(1 << 33), ..RttStats::default() };
+        assert_eq!(stats.average(), Some(Duration::from_secs(1)));
+    }
+}
@@ -0,0 +1,403 @@
+use std::fmt;
+use std::net::IpAddr;
+use crate::arp::{self, ArpPacket};
+use crate::checksum::{checksum_status, ones_complement_sum, pseudo_header_sum, ChecksumStatus};
+use crate::defrag::Reassembled;
+use crate::error::ParseError;
+use crate::ethernet::{self, EtherType, EthernetFrame};
+use crate::icmp::{self, IcmpPacket};
+use crate::icmpv6::{self, Icmpv6Packet};
+use crate::ipv4::{self, IPType, IPv4Datagram};
+use crate::ipv6::{self, IPv6Datagram};
+use crate::loopback::{self, LoopbackHeader};
+use crate::ppp::{self, PppHeader};
+use crate::sll::{self, Sll2Header, SllHeader};
+use crate::tcp::{self, TcpSegment};
+use crate::udp::{self, UdpDatagram, UdpLengthStatus};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkType {
+    Null,
+    Ethernet,
+    PPP,
+    Raw,
+    Loop,
+    LinuxSLL,
+    IPv4,
+    IPv6,
+    LinuxSLL2,
+    Other(i32),
+}
+
+impl From<i32> for LinkType {
+    fn from(raw: i32) -> Self {
+        match raw {
+            0 => Self::Null,
+            1 => Self::Ethernet,
+            9 | 50 => Self::PPP,
+            12 | 14 | 101 => Self::Raw,
+            108 => Self::Loop,
+            113 => Self::LinuxSLL,
+            228 => Self::IPv4,
+            229 => Self::IPv6,
+            276 => Self::LinuxSLL2,
+            other => Self::Other(other),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Ethernet,
+    LinuxSLL,
+    LinuxSLL2,
+    Loopback,
+    PPP,
+    IPv4,
+    IPv6,
+    ARP,
+    TCP,
+    UDP,
+    ICMP,
+    ICMPv6,
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Ethernet => "Ethernet",
+            Self::LinuxSLL => "Linux cooked",
+            Self::LinuxSLL2 => "Linux cooked v2",
+            Self::Loopback => "Loopback",
+            Self::PPP => "PPP",
+            Self::IPv4 => "IPv4",
+            Self::IPv6 => "IPv6",
+            Self::ARP => "ARP",
+            Self::TCP => "TCP",
+            Self::UDP => "UDP",
+            Self::ICMP => "ICMP",
+            Self::ICMPv6 => "ICMPv6",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug)]
+pub enum LinkLayer {
+    Ethernet(EthernetFrame),
+    LinuxSLL(SllHeader),
+    LinuxSLL2(Sll2Header),
+    Loopback(LoopbackHeader),
+    PPP(PppHeader),
+    Raw,
+}
+
+#[derive(Debug)]
+pub enum NetworkLayer {
+    IPv4(IPv4Datagram),
+    IPv6(IPv6Datagram),
+    ARP(ArpPacket),
+}
+
+#[derive(Debug)]
+pub enum TransportLayer {
+    TCP(TcpSegment),
+    UDP(UdpDatagram),
+    ICMP(IcmpPacket),
+    ICMPv6(Icmpv6Packet),
+}
+
+#[derive(Debug)]
+pub struct DissectError {
+    pub protocol: Protocol,
+    pub error: ParseError,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PseudoHeader {
+    source: IpAddr,
+    dest: IpAddr,
+    length: usize,
+}
+
+impl PseudoHeader {
+    fn verify(&self, protocol: u8, input: &[u8], transmitted: u16) -> ChecksumStatus {
+        match input.get(..self.length) {
+            Some(data) => {
+                let sum = pseudo_header_sum(self.source, self.dest, protocol, self.length);
+                checksum_status(ones_complement_sum(sum, data), transmitted)
+            }
+            None => ChecksumStatus::Unverified,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Packet<'a> {
+    pub link: Option<LinkLayer>,
+    pub network: Option<NetworkLayer>,
+    pub transport: Option<TransportLayer>,
+    pub payload: &'a [u8],
+    pub error: Option<DissectError>,
+}
+
+impl<'a> Packet<'a> {
+    pub fn ethernet(&self) -> Option<&EthernetFrame> {
+        match &self.link {
+            Some(LinkLayer::Ethernet(frame)) => Some(frame),
+            _ => None,
+        }
+    }
+
+    pub fn ipv4(&self) -> Option<&IPv4Datagram> {
+        match &self.network {
+            Some(NetworkLayer::IPv4(datagram)) => Some(datagram),
+            _ => None,
+        }
+    }
+
+    pub fn ipv6(&self) -> Option<&IPv6Datagram> {
+        match &self.network {
+            Some(NetworkLayer::IPv6(datagram)) => Some(datagram),
+            _ => None,
+        }
+    }
+
+    pub fn ip_addresses(&self) -> Option<(IpAddr, IpAddr)> {
+        match &self.network {
+            Some(NetworkLayer::IPv4(datagram)) => Some((IpAddr::V4(datagram.source_addr), IpAddr::V4(datagram.dest_addr))),
+            Some(NetworkLayer::IPv6(datagram)) => Some((IpAddr::V6(datagram.source_addr), IpAddr::V6(datagram.dest_addr))),
+            _ => None,
+        }
+    }
+
+    pub fn arp(&self) -> Option<&ArpPacket> {
+        match &self.network {
+            Some(NetworkLayer::ARP(packet)) => Some(packet),
+            _ => None,
+        }
+    }
+
+    pub fn tcp(&self) -> Option<&TcpSegment> {
+        match &self.transport {
+            Some(TransportLayer::TCP(segment)) => Some(segment),
+            _ => None,
+        }
+    }
+
+    pub fn udp(&self) -> Option<&UdpDatagram> {
+        match &self.transport {
+            Some(TransportLayer::UDP(datagram)) => Some(datagram),
+            _ => None,
+        }
+    }
+
+    pub fn icmp(&self) -> Option<&IcmpPacket> {
+        match &self.transport {
+            Some(TransportLayer::ICMP(packet)) => Some(packet),
+            _ => None,
+        }
+    }
+
+    pub fn icmpv6(&self) -> Option<&Icmpv6Packet> {
+        match &self.transport {
+            Some(TransportLayer::ICMPv6(packet)) => Some(packet),
+            _ => None,
+        }
+    }
+
+    fn fail(&mut self, protocol: Protocol, error: ParseError) {
+        self.error = Some(DissectError { protocol, error });
+    }
+
+    fn dissect_link<T, F, G>(&mut self, protocol: Protocol, input: &'a [u8], parse: F, wrap: G)
+    where
+        F: Fn(&'a [u8]) -> Result<(&'a [u8], T), ParseError>,
+        G: Fn(T) -> (LinkLayer, EtherType),
+    {
+        match parse(input) {
+            Ok((payload, header)) => {
+                let (link, ethertype) = wrap(header);
+                self.link = Some(link);
+                self.payload = payload;
+                self.dissect_ethertype(ethertype, payload);
+            }
+            Err(e) => self.fail(protocol, e),
+        }
+    }
+
+    fn dissect_raw(&mut self, input: &'a [u8]) {
+        self.link = Some(LinkLayer::Raw);
+        match input.first().map(|byte| byte >> 4) {
+            Some(4) => self.dissect_ethertype(EtherType::IPv4, input),
+            Some(6) => self.dissect_ethertype(EtherType::IPv6, input),
+            Some(version) => self.fail(Protocol::IPv4, ParseError::InvalidValue { field: "ip.version", value: version as u32 }),
+            None => self.fail(Protocol::IPv4, ParseError::Truncated { needed: 1, available: 0 }),
+        }
+    }
+
+    fn dissect_ipv4(&mut self, datagram: IPv4Datagram, payload: &'a [u8]) {
+        let protocol = datagram.protocol;
+        let is_fragment = datagram.is_fragment();
+        let header_length = datagram.header_length as usize * 4;
+        let pseudo_header = (datagram.length as usize).checked_sub(header_length).map(|length| PseudoHeader {
+            source: IpAddr::V4(datagram.source_addr),
+            dest: IpAddr::V4(datagram.dest_addr),
+            length,
+        });
+        self.network = Some(NetworkLayer::IPv4(datagram));
+        self.payload = payload;
+        if !is_fragment {
+            self.dissect_ip_payload(protocol, payload, pseudo_header);
+        }
+    }
+
+    fn dissect_ethertype(&mut self, ethertype: EtherType, input: &'a [u8]) {
+        match ethertype {
+            EtherType::IPv4 => match ipv4::parse_ipv4(input) {
+                Ok((payload, datagram)) => self.dissect_ipv4(datagram, payload),
+                Err(e) => self.fail(Protocol::IPv4, e),
+            },
+            EtherType::IPv6 => match ipv6::parse_ipv6(input) {
+                Ok((payload, datagram)) => {
+                    let protocol = datagram.protocol;
+                    let is_fragment = datagram.is_fragment();
+                    let pseudo_header = match (datagram.payload_length as usize).checked_sub(datagram.extension_length) {
+                        Some(length) if datagram.payload_length != 0 => Some(PseudoHeader {
+                            source: IpAddr::V6(datagram.source_addr),
+                            dest: IpAddr::V6(datagram.dest_addr),
+                            length,
+                        }),
+                        _ => None,
+                    };
+                    self.network = Some(NetworkLayer::IPv6(datagram));
+                    self.payload = payload;
+                    if !is_fragment {
+                        self.dissect_ip_payload(protocol, payload, pseudo_header);
+                    }
+                }
+                Err(e) => self.fail(Protocol::IPv6, e),
+            },
+            // RARP uses the same packet format as ARP.
+            EtherType::ARP | EtherType::RARP => match arp::parse_arp(input) {
+                Ok((payload, packet)) => {
+                    self.network = Some(NetworkLayer::ARP(packet));
+                    self.payload = payload;
+                }
+                Err(e) => self.fail(Protocol::ARP, e),
+            },
+            _ => {}
+        }
+    }
+
+    fn dissect_ip_payload(&mut self, protocol: IPType, input: &'a [u8], pseudo_header: Option<PseudoHeader>) {
+        match protocol {
+            IPType::TCP => match tcp::parse_tcp(input) {
+                Ok((mut payload, mut segment)) => {
+                    if let Some(pseudo_header) = pseudo_header {
+                        segment.checksum_status = pseudo_header.verify(6, input, segment.checksum);
+                        // Drop link layer padding after the end of the IP datagram.
+                        let header_length = input.len() - payload.len();
+                        let length = pseudo_header.length.saturating_sub(header_length);
+                        payload = &payload[..length.min(payload.len())];
+                    }
+                    self.transport = Some(TransportLayer::TCP(segment));
+                    self.payload = payload;
+                }
+                Err(e) => self.fail(Protocol::TCP, e),
+            },
+            IPType::UDP => match udp::parse_udp(input) {
+                Ok((mut payload, mut datagram)) => {
+                    let length = datagram.length as usize;
+                    if let Some(pseudo_header) = &pseudo_header {
+                        let ip_length = pseudo_header.length;
+                        if datagram.length_status != UdpLengthStatus::TooShort {
+                            // A truncated capture is the more useful thing to report.
+                            if datagram.length_status == UdpLengthStatus::Good && length != ip_length {
+                                datagram.length_status = UdpLengthStatus::IpMismatch { ip_length };
+                            }
+                            // Anything past the end of the IP datagram is link layer padding.
+                            datagram.padding = input.len().min(ip_length).saturating_sub(length);
+                        }
+                        payload = &payload[..ip_length.saturating_sub(8).min(payload.len())];
+                    }
+                    // A zero checksum means the sender did not compute one, which is only allowed over IPv4.
+                    // The checksum covers the datagram as long as its own length field says it is.
+                    datagram.checksum_status = match pseudo_header {
+                        Some(pseudo_header) if datagram.checksum == 0 && pseudo_header.source.is_ipv4() => {
+                            ChecksumStatus::NotPresent
+                        }
+                        Some(_) if datagram.length_status == UdpLengthStatus::TooShort => ChecksumStatus::Unverified,
+                        Some(pseudo_header) => PseudoHeader { length, ..pseudo_header }.verify(17, input, datagram.checksum),
+                        None => ChecksumStatus::Unverified,
+                    };
+                    self.transport = Some(TransportLayer::UDP(datagram));
+                    self.payload = payload;
+                }
+                Err(e) => self.fail(Protocol::UDP, e),
+            },
+            IPType::ICMP => match icmp::parse_icmp(input) {
+                Ok((payload, packet)) => {
+                    self.transport = Some(TransportLayer::ICMP(packet));
+                    self.payload = payload;
+                }
+                Err(e) => self.fail(Protocol::ICMP, e),
+            },
+            IPType::ICMPv6 => match icmpv6::parse_icmpv6(input) {
+                Ok((payload, packet)) => {
+                    self.transport = Some(TransportLayer::ICMPv6(packet));
+                    self.payload = payload;
+                }
+                Err(e) => self.fail(Protocol::ICMPv6, e),
+            },
+            IPType::Other(_) => {}
+        }
+    }
+}
+
+pub fn dissect_reassembled(reassembled: &Reassembled) -> Packet<'_> {
+    let mut packet = Packet { link: None, network: None, transport: None, payload: &reassembled.payload, error: None };
+    packet.dissect_ipv4(reassembled.datagram.clone(), &reassembled.payload);
+    packet
+}
+
+pub fn dissect(linktype: LinkType, data: &[u8]) -> Packet<'_> {
+    let mut packet = Packet { link: None, network: None, transport: None, payload: data, error: None };
+    match linktype {
+        LinkType::Ethernet => packet.dissect_link(Protocol::Ethernet, data, ethernet::parse_ethernet, |frame| {
+            let ethertype = frame.ethertype;
+            (LinkLayer::Ethernet(frame), ethertype)
+        }),
+        LinkType::LinuxSLL => packet.dissect_link(Protocol::LinuxSLL, data, sll::parse_sll, |header| {
+            let ethertype = header.protocol;
+            (LinkLayer::LinuxSLL(header), ethertype)
+        }),
+        LinkType::LinuxSLL2 => packet.dissect_link(Protocol::LinuxSLL2, data, sll::parse_sll2, |header| {
+            let ethertype = header.protocol;
+            (LinkLayer::LinuxSLL2(header), ethertype)
+        }),
+        LinkType::Null => packet.dissect_link(Protocol::Loopback, data, loopback::parse_null, |header| {
+            let ethertype = EtherType::from(header.family);
+            (LinkLayer::Loopback(header), ethertype)
+        }),
+        LinkType::Loop => packet.dissect_link(Protocol::Loopback, data, loopback::parse_loop, |header| {
+            let ethertype = EtherType::from(header.family);
+            (LinkLayer::Loopback(header), ethertype)
+        }),
+        LinkType::PPP => packet.dissect_link(Protocol::PPP, data, ppp::parse_ppp, |header| {
+            let ethertype = EtherType::from(header.protocol);
+            (LinkLayer::PPP(header), ethertype)
+        }),
+        LinkType::Raw => packet.dissect_raw(data),
+        LinkType::IPv4 => {
+            packet.link = Some(LinkLayer::Raw);
+            packet.dissect_ethertype(EtherType::IPv4, data);
+        }
+        LinkType::IPv6 => {
+            packet.link = Some(LinkLayer::Raw);
+            packet.dissect_ethertype(EtherType::IPv6, data);
+        }
+        LinkType::Other(_) => {}
+    }
+    packet
+}
@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::time::Duration;
+use crate::icmp::IcmpMessage;
+use crate::icmpv6::Icmpv6Message;
+use crate::metrics::RttStats;
+use crate::packet::Packet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EchoResult {
+    Request,
+    Reply { response_time: Duration },
+    // A further reply to a request that was already answered.
+    DuplicateReply { response_time: Duration },
+    // A reply to a request that was not captured or has timed out.
+    NoRequest,
+}
+
+impl fmt::Display for EchoResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Request => write!(f, "Echo request"),
+            Self::Reply { response_time } => {
+                write!(f, "Response time: {:.3} ms", response_time.as_secs_f64() * 1000.0)
+            }
+            Self::DuplicateReply { response_time } => {
+                write!(f, "Duplicate reply, response time: {:.3} ms", response_time.as_secs_f64() * 1000.0)
+            }
+            Self::NoRequest => write!(f, "No request seen"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PingConfig {
+    // A request without a reply after this long is counted as lost.
+    pub timeout: Duration,
+}
+
+impl Default for PingConfig {
+    fn default() -> Self {
+        PingConfig { timeout: Duration::from_secs(10) }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HostSummary {
+    pub host: IpAddr,
+    pub requests: u64,
+    pub replies: u64,
+    pub duplicates: u64,
+    pub lost: u64,
+    pub unsolicited: u64,
+    pub response_times: RttStats,
+}
+
+impl HostSummary {
+    fn new(host: IpAddr) -> Self {
+        HostSummary {
+            host,
+            requests: 0,
+            replies: 0,
+            duplicates: 0,
+            lost: 0,
+            unsolicited: 0,
+            response_times: RttStats::default(),
+        }
+    }
+
+    // Share of the requests that were lost, as a percentage.
+    pub fn loss(&self) -> f64 {
+        match self.requests {
+            0 => 0.0,
+            requests => self.lost as f64 * 100.0 / requests as f64,
+        }
+    }
+}
+
+fn millis(duration: Option<Duration>) -> f64 {
+    duration.map_or(0.0, |duration| duration.as_secs_f64() * 1000.0)
+}
+
+impl fmt::Display for HostSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} requests, {} replies, {:.1}% loss, {} duplicates, {} without request",
+            self.host,
+            self.requests,
+            self.replies,
+            self.loss(),
+            self.duplicates,
+            self.unsolicited
+        )?;
+        if self.response_times.samples > 0 {
+            write!(
+                f,
+                ", rtt min/avg/max/jitter = {:.3}/{:.3}/{:.3}/{:.3} ms",
+                millis(self.response_times.min),
+                millis(self.response_times.average()),
+                millis(self.response_times.max),
+                millis(self.response_times.jitter())
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct EchoKey {
+    requester: IpAddr,
+    target: IpAddr,
+    identifier: u16,
+    sequence_no: u16,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PendingRequest {
+    sent: Duration,
+    replies: u64,
+}
+
+#[derive(Debug)]
+pub struct PingTracker {
+    config: PingConfig,
+    pending: HashMap<EchoKey, PendingRequest>,
+    hosts: HashMap<IpAddr, HostSummary>,
+}
+
+impl PingTracker {
+    pub fn new(config: PingConfig) -> Self {
+        PingTracker { config, pending: HashMap::new(), hosts: HashMap::new() }
+    }
+
+    // Summaries of every pinged host, sorted by address.
+    pub fn summaries(&self) -> Vec<&HostSummary> {
+        let mut summaries: Vec<&HostSummary> = self.hosts.values().collect();
+        summaries.sort_by_key(|summary| summary.host);
+        summaries
+    }
+
+    fn host(&mut self, host: IpAddr) -> &mut HostSummary {
+        self.hosts.entry(host).or_insert_with(|| HostSummary::new(host))
+    }
+
+    fn forget(&mut self, key: EchoKey, request: PendingRequest) {
+        if request.replies == 0 {
+            self.host(key.target).lost += 1;
+        }
+    }
+
+    pub fn expire(&mut self, now: Duration) {
+        let timeout = self.config.timeout;
+        let expired: Vec<EchoKey> = self
+            .pending
+            .iter()
+            .filter(|(_, request)| now.saturating_sub(request.sent) > timeout)
+            .map(|(key, _)| *key)
+            .collect();
+        for key in expired {
+            if let Some(request) = self.pending.remove(&key) {
+                self.forget(key, request);
+            }
+        }
+    }
+
+    // Counts the requests still waiting for a reply as lost.
+    pub fn flush(&mut self) {
+        let pending: Vec<(EchoKey, PendingRequest)> = self.pending.drain().collect();
+        for (key, request) in pending {
+            self.forget(key, request);
+        }
+    }
+
+    pub fn process(&mut self, now: Duration, packet: &Packet) -> Option<EchoResult> {
+        let (source, dest) = packet.ip_addresses()?;
+        let (request, identifier, sequence_no) = match (packet.icmp(), packet.icmpv6()) {
+            (Some(icmp), _) => match icmp.message {
+                IcmpMessage::Echo { identifier, sequence_no } => (icmp.message_type == 8, identifier, sequence_no),
+                _ => return None,
+            },
+            (_, Some(icmpv6)) => match icmpv6.message {
+                Icmpv6Message::Echo { identifier, sequence_no } => (icmpv6.message_type == 128, identifier, sequence_no),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        if request {
+            let key = EchoKey { requester: source, target: dest, identifier, sequence_no };
+            self.host(dest).requests += 1;
+            // A request sent again with the same sequence number replaces the first one.
+            if let Some(previous) = self.pending.insert(key, PendingRequest { sent: now, replies: 0 }) {
+                self.forget(key, previous);
+            }
+            return Some(EchoResult::Request);
+        }
+        let key = EchoKey { requester: dest, target: source, identifier, sequence_no };
+        let pending = match self.pending.get_mut(&key) {
+            Some(pending) => pending,
+            None => {
+                self.host(source).unsolicited += 1;
+                return Some(EchoResult::NoRequest);
+            }
+        };
+        pending.replies += 1;
+        let response_time = now.saturating_sub(pending.sent);
+        let duplicate = pending.replies > 1;
+        let host = self.host(source);
+        if duplicate {
+            host.duplicates += 1;
+            Some(EchoResult::DuplicateReply { response_time })
+        } else {
+            host.replies += 1;
+            host.response_times.record(response_time);
+            Some(EchoResult::Reply { response_time })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use crate::packet::{dissect, LinkType};
+
+    const HOST: [u8; 4] = [10, 0, 0, 1];
+    const TARGET: [u8; 4] = [10, 0, 0, 2];
+
+    fn echo(request: bool, sequence_no: u16) -> Vec<u8> {
+        let (source, dest, message_type) = if request { (HOST, TARGET, 8) } else { (TARGET, HOST, 0) };
+        let mut packet: Vec<u8> = vec![0x45, 0, 0, 28, 0, 1, 0x40, 0, 64, 1, 0, 0];
+        packet.extend_from_slice(&source);
+        packet.extend_from_slice(&dest);
+        packet.extend_from_slice(&[message_type, 0, 0, 0, 0x12, 0x34]);
+        packet.extend_from_slice(&sequence_no.to_be_bytes());
+        packet
+    }
+
+    fn send(tracker: &mut PingTracker, millis: u64, request: bool, sequence_no: u16) -> Option<EchoResult> {
+        tracker.process(Duration::from_millis(millis), &dissect(LinkType::IPv4, &echo(request, sequence_no)))
+    }
+
+    fn target_summary(tracker: &PingTracker) -> HostSummary {
+        let summaries = tracker.summaries();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].host, IpAddr::V4(Ipv4Addr::from(TARGET)));
+        *summaries[0]
+    }
+
+    fn reply(millis: u64) -> Option<EchoResult> {
+        Some(EchoResult::Reply { response_time: Duration::from_millis(millis) })
+    }
+
+    #[test]
+    fn pairs_replies_with_requests() {
+        let mut tracker = PingTracker::new(PingConfig::default());
+        assert_eq!(send(&mut tracker, 0, true, 1), Some(EchoResult::Request));
+        assert_eq!(send(&mut tracker, 1000, true, 2), Some(EchoResult::Request));
+        assert_eq!(send(&mut tracker, 1030, false, 2), reply(30));
+        assert_eq!(send(&mut tracker, 1040, false, 1), reply(1040));
+        tracker.flush();
+        let summary = target_summary(&tracker);
+        assert_eq!((summary.requests, summary.replies, summary.lost), (2, 2, 0));
+        assert_eq!(summary.response_times.min, Some(Duration::from_millis(30)));
+        assert_eq!(summary.response_times.max, Some(Duration::from_millis(1040)));
+    }
+
+    #[test]
+    fn duplicate_replies_and_replies_without_request() {
+        let mut tracker = PingTracker::new(PingConfig::default());
+        send(&mut tracker, 0, true, 1);
+        assert_eq!(send(&mut tracker, 10, false, 1), reply(10));
+        assert_eq!(
+            send(&mut tracker, 15, false, 1),
+            Some(EchoResult::DuplicateReply { response_time: Duration::from_millis(15) })
+        );
+        assert_eq!(send(&mut tracker, 20, false, 7), Some(EchoResult::NoRequest));
+        let summary = target_summary(&tracker);
+        assert_eq!((summary.replies, summary.duplicates, summary.unsolicited), (1, 1, 1));
+        assert_eq!(summary.response_times.samples, 1);
+    }
+
+    #[test]
+    fn unanswered_requests_are_lost() {
+        let mut tracker = PingTracker::new(PingConfig::default());
+        send(&mut tracker, 0, true, 1);
+        send(&mut tracker, 5000, true, 2);
+        send(&mut tracker, 6000, true, 3);
+        send(&mut tracker, 6010, false, 3);
+        tracker.expire(Duration::from_millis(10_000));
+        assert_eq!(target_summary(&tracker).lost, 0);
+        tracker.expire(Duration::from_millis(10_001));
+        assert_eq!(target_summary(&tracker).lost, 1);
+        // Too late to count.
+        assert_eq!(send(&mut tracker, 10_010, false, 1), Some(EchoResult::NoRequest));
+
+        tracker.flush();
+        let summary = target_summary(&tracker);
+        assert_eq!((summary.requests, summary.replies, summary.lost), (3, 1, 2));
+        assert_eq!(summary.loss(), 200.0 / 3.0);
+    }
+
+    #[test]
+    fn resent_request_replaces_the_first() {
+        let mut tracker = PingTracker::new(PingConfig::default());
+        send(&mut tracker, 0, true, 1);
+        send(&mut tracker, 1000, true, 1);
+        assert_eq!(send(&mut tracker, 1020, false, 1), reply(20));
+        tracker.flush();
+        let summary = target_summary(&tracker);
+        assert_eq!((summary.requests, summary.replies, summary.lost), (2, 1, 1));
+
+        // An answered request sent again is not lost when it is replaced.
+        let mut tracker = PingTracker::new(PingConfig::default());
+        send(&mut tracker, 0, true, 1);
+        send(&mut tracker, 10, false, 1);
+        send(&mut tracker, 1000, true, 1);
+        assert_eq!(send(&mut tracker, 1010, false, 1), reply(10));
+        tracker.flush();
+        let summary = target_summary(&tracker);
+        assert_eq!((summary.requests, summary.replies, summary.duplicates, summary.lost), (2, 2, 0, 0));
+    }
+
+    #[test]
+    fn other_icmp_messages_are_ignored() {
+        let mut tracker = PingTracker::new(PingConfig::default());
+        let mut bytes = echo(true, 1);
+        bytes[20] = 13;
+        bytes.extend_from_slice(&[0; 12]);
+        bytes[3] = 40;
+        assert_eq!(tracker.process(Duration::ZERO, &dissect(LinkType::IPv4, &bytes)), None);
+        assert!(tracker.summaries().is_empty());
+    }
+}
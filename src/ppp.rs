@@ -0,0 +1,60 @@
+use crate::bytes::{read_u16, read_u8, skip};
+use crate::error::ParseError;
+use crate::ethernet::EtherType;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PppProtocol {
+    IPv4,
+    IPv6,
+    LinkControl,
+    IpControl,
+    Other(u16),
+}
+
+impl From<u16> for PppProtocol {
+    fn from(raw: u16) -> Self {
+        match raw {
+            0x0021 => Self::IPv4,
+            0x0057 => Self::IPv6,
+            0xC021 => Self::LinkControl,
+            0x8021 => Self::IpControl,
+            other => Self::Other(other),
+        }
+    }
+}
+
+impl From<PppProtocol> for EtherType {
+    fn from(protocol: PppProtocol) -> Self {
+        match protocol {
+            PppProtocol::IPv4 => EtherType::IPv4,
+            PppProtocol::IPv6 => EtherType::IPv6,
+            _ => EtherType::Other(0),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PppHeader {
+    pub address: Option<u8>,
+    pub control: Option<u8>,
+    pub protocol: PppProtocol,
+}
+
+pub fn parse_ppp(input: &[u8]) -> Result<(&[u8], PppHeader), ParseError> {
+    // The address and control fields are omitted when address-and-control-field
+    // compression was negotiated.
+    let (address, control, input) = if read_u8(input, 0)? == 0xFF {
+        (Some(0xFF), Some(read_u8(input, 1)?), skip(input, 2)?)
+    } else {
+        (None, None, input)
+    };
+    // A compressed protocol field is a single byte, recognizable by its low bit.
+    let (protocol, input) = if read_u8(input, 0)? & 1 == 1 {
+        (read_u8(input, 0)? as u16, skip(input, 1)?)
+    } else {
+        (read_u16(input, 0)?, skip(input, 2)?)
+    };
+
+    let header = PppHeader { address, control, protocol: PppProtocol::from(protocol) };
+    Ok((input, header))
+}
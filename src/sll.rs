@@ -0,0 +1,72 @@
+use crate::bytes::{ensure_length, read_u16, read_u32, read_u8};
+use crate::error::ParseError;
+use crate::ethernet::EtherType;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketType {
+    Host,
+    Broadcast,
+    Multicast,
+    OtherHost,
+    Outgoing,
+    Other(u16),
+}
+
+impl From<u16> for PacketType {
+    fn from(raw: u16) -> Self {
+        match raw {
+            0 => Self::Host,
+            1 => Self::Broadcast,
+            2 => Self::Multicast,
+            3 => Self::OtherHost,
+            4 => Self::Outgoing,
+            other => Self::Other(other),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SllHeader {
+    pub packet_type: PacketType,
+    pub arphrd_type: u16,
+    pub address: Vec<u8>,
+    pub protocol: EtherType,
+}
+
+#[derive(Debug)]
+pub struct Sll2Header {
+    pub protocol: EtherType,
+    pub interface_index: u32,
+    pub arphrd_type: u16,
+    pub packet_type: PacketType,
+    pub address: Vec<u8>,
+}
+
+pub fn parse_sll(input: &[u8]) -> Result<(&[u8], SllHeader), ParseError> {
+    ensure_length(input, 16)?;
+    let packet_type = PacketType::from(read_u16(input, 0)?);
+    let arphrd_type = read_u16(input, 2)?;
+    let address_length = read_u16(input, 4)?.min(8) as usize;
+    let address = input[6..6 + address_length].to_vec();
+    let protocol = EtherType::from(read_u16(input, 14)?);
+
+    let (_, input) = input.split_at(16);
+
+    let header = SllHeader { packet_type, arphrd_type, address, protocol };
+    Ok((input, header))
+}
+
+pub fn parse_sll2(input: &[u8]) -> Result<(&[u8], Sll2Header), ParseError> {
+    ensure_length(input, 20)?;
+    let protocol = EtherType::from(read_u16(input, 0)?);
+    let interface_index = read_u32(input, 4)?;
+    let arphrd_type = read_u16(input, 8)?;
+    let packet_type = PacketType::from(read_u8(input, 10)? as u16);
+    let address_length = read_u8(input, 11)?.min(8) as usize;
+    let address = input[12..12 + address_length].to_vec();
+
+    let (_, input) = input.split_at(20);
+
+    let header = Sll2Header { protocol, interface_index, arphrd_type, packet_type, address };
+    Ok((input, header))
+}
@@ -0,0 +1,148 @@
+use std::fmt;
+use crate::checksum::ChecksumStatus;
+use crate::ipv4::Ecn;
+use crate::packet::Packet;
+use crate::tcp::TcpFlags;
+use crate::udp::UdpLengthStatus;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ChecksumCounts {
+    pub good: u64,
+    pub bad: u64,
+    pub unverified: u64,
+    pub not_present: u64,
+}
+
+impl ChecksumCounts {
+    pub fn record(&mut self, status: ChecksumStatus) {
+        match status {
+            ChecksumStatus::Good => self.good += 1,
+            ChecksumStatus::Bad { .. } => self.bad += 1,
+            ChecksumStatus::Unverified => self.unverified += 1,
+            ChecksumStatus::NotPresent => self.not_present += 1,
+        }
+    }
+}
+
+impl fmt::Display for ChecksumCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} good, {} bad, {} unverified, {} not present",
+            self.good, self.bad, self.unverified, self.not_present
+        )
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EcnCounts {
+    pub ect: u64,
+    pub congestion_experienced: u64,
+    pub setup_syn: u64,
+    pub setup_syn_ack: u64,
+    pub ece: u64,
+    pub cwr: u64,
+}
+
+impl EcnCounts {
+    pub fn record_ip(&mut self, ecn: Ecn) {
+        match ecn {
+            Ecn::Ect0 | Ecn::Ect1 => self.ect += 1,
+            Ecn::CongestionExperienced => self.congestion_experienced += 1,
+            Ecn::NotEct => {}
+        }
+    }
+
+    pub fn record_tcp(&mut self, flags: TcpFlags) {
+        if flags.is_ecn_setup_syn() {
+            self.setup_syn += 1;
+        } else if flags.is_ecn_setup_syn_ack() {
+            self.setup_syn_ack += 1;
+        } else {
+            if flags.contains(TcpFlags::ECE) {
+                self.ece += 1;
+            }
+            if flags.contains(TcpFlags::CWR) {
+                self.cwr += 1;
+            }
+        }
+    }
+}
+
+impl fmt::Display for EcnCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} ECT, {} CE, {} setup SYN, {} setup SYN-ACK, {} ECE, {} CWR",
+            self.ect, self.congestion_experienced, self.setup_syn, self.setup_syn_ack, self.ece, self.cwr
+        )
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CaptureStats {
+    pub packets: u64,
+    pub reassembled_datagrams: u64,
+    pub parse_errors: u64,
+    pub ipv4_checksums: ChecksumCounts,
+    pub tcp_checksums: ChecksumCounts,
+    pub udp_checksums: ChecksumCounts,
+    pub udp_bad_lengths: u64,
+    pub udp_padded: u64,
+    pub ecn: EcnCounts,
+}
+
+impl CaptureStats {
+    pub fn record(&mut self, packet: &Packet) {
+        self.packets += 1;
+        if let Some(datagram) = packet.ipv4() {
+            self.ecn.record_ip(datagram.ecn);
+            self.ipv4_checksums.record(datagram.checksum_status);
+        }
+        if let Some(datagram) = packet.ipv6() {
+            self.ecn.record_ip(datagram.ecn);
+        }
+        self.record_layers(packet);
+    }
+
+    // The IPv4 header of a reassembled datagram is the first fragment's, which
+    // was already counted by `record`, so only the layers above it are recorded.
+    pub fn record_reassembled(&mut self, packet: &Packet) {
+        self.reassembled_datagrams += 1;
+        self.record_layers(packet);
+    }
+
+    fn record_layers(&mut self, packet: &Packet) {
+        if packet.error.is_some() {
+            self.parse_errors += 1;
+        }
+        if let Some(segment) = packet.tcp() {
+            self.tcp_checksums.record(segment.checksum_status);
+            self.ecn.record_tcp(segment.flags);
+        }
+        if let Some(datagram) = packet.udp() {
+            self.udp_checksums.record(datagram.checksum_status);
+            if datagram.length_status != UdpLengthStatus::Good {
+                self.udp_bad_lengths += 1;
+            }
+            if datagram.padding > 0 {
+                self.udp_padded += 1;
+            }
+        }
+    }
+}
+
+impl fmt::Display for CaptureStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} packets, {} reassembled datagrams, {} with parse errors",
+            self.packets, self.reassembled_datagrams, self.parse_errors
+        )?;
+        writeln!(f, "IPv4 header checksums: {}", self.ipv4_checksums)?;
+        writeln!(f, "TCP checksums: {}", self.tcp_checksums)?;
+        writeln!(f, "UDP checksums: {}", self.udp_checksums)?;
+        writeln!(f, "UDP lengths: {} bad, {} padded", self.udp_bad_lengths, self.udp_padded)?;
+        write!(f, "ECN: {}", self.ecn)
+    }
+}
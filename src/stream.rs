@@ -0,0 +1,428 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use crate::flow::{CloseReason, Endpoint};
+use crate::packet::Packet;
+use crate::tcp::TcpFlags;
+
+// One direction of a TCP connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StreamKey {
+    pub source: Endpoint,
+    pub dest: Endpoint,
+}
+
+impl StreamKey {
+    pub fn reverse(&self) -> StreamKey {
+        StreamKey { source: self.dest, dest: self.source }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamEvent {
+    // `offset` counts bytes from the start of the stream, so it keeps growing
+    // past sequence number wraparound.
+    Data { stream: StreamKey, offset: u64, data: Vec<u8> },
+    Gap { stream: StreamKey, offset: u64, length: u64 },
+    End { stream: StreamKey, reason: CloseReason },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamConfig {
+    pub idle_timeout: Duration,
+    // Out-of-order data buffered per direction before giving up on the missing bytes.
+    pub max_buffered_bytes: usize,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        StreamConfig {
+            idle_timeout: Duration::from_secs(300),
+            max_buffered_bytes: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct HalfStream {
+    // Sequence number of the byte at offset 0.
+    base_seq: u32,
+    next_offset: u64,
+    pending: BTreeMap<u64, Vec<u8>>,
+    pending_bytes: usize,
+    fin_offset: Option<u64>,
+    last_seen: Duration,
+    // Closed streams are kept until they time out so retransmissions do not
+    // open a new one.
+    closed: bool,
+}
+
+impl HalfStream {
+    fn new(base_seq: u32, now: Duration) -> Self {
+        HalfStream {
+            base_seq,
+            next_offset: 0,
+            pending: BTreeMap::new(),
+            pending_bytes: 0,
+            fin_offset: None,
+            last_seen: now,
+            closed: false,
+        }
+    }
+
+    // Maps a sequence number to a stream offset close to the current position,
+    // or None if it falls before the start of the stream.
+    fn offset(&self, sequence_no: u32) -> Option<u64> {
+        let expected = self.base_seq.wrapping_add(self.next_offset as u32);
+        let offset = self.next_offset as i64 + sequence_no.wrapping_sub(expected) as i32 as i64;
+        u64::try_from(offset).ok()
+    }
+
+    fn deliver<F: FnMut(StreamEvent)>(&mut self, stream: StreamKey, emit: &mut F) {
+        while let Some(entry) = self.pending.first_entry() {
+            let offset = *entry.key();
+            if offset > self.next_offset {
+                break;
+            }
+            let data = entry.remove();
+            self.pending_bytes -= data.len();
+            let end = offset + data.len() as u64;
+            if end > self.next_offset {
+                let data = data[(self.next_offset - offset) as usize..].to_vec();
+                emit(StreamEvent::Data { stream, offset: self.next_offset, data });
+                self.next_offset = end;
+            }
+        }
+    }
+
+    fn skip_to<F: FnMut(StreamEvent)>(&mut self, stream: StreamKey, offset: u64, emit: &mut F) {
+        if offset > self.next_offset {
+            emit(StreamEvent::Gap { stream, offset: self.next_offset, length: offset - self.next_offset });
+            self.next_offset = offset;
+        }
+        self.deliver(stream, emit);
+    }
+
+    fn add<F: FnMut(StreamEvent)>(&mut self, stream: StreamKey, offset: u64, data: &[u8], max_buffered_bytes: usize, emit: &mut F) {
+        let end = offset + data.len() as u64;
+        if end <= self.next_offset {
+            // Retransmission of data that was already delivered.
+            return;
+        }
+        let replaced = match self.pending.get(&offset) {
+            Some(existing) if existing.len() >= data.len() => return,
+            Some(existing) => existing.len(),
+            None => 0,
+        };
+        self.pending.insert(offset, data.to_vec());
+        self.pending_bytes += data.len() - replaced;
+        self.deliver(stream, emit);
+        while self.pending_bytes > max_buffered_bytes {
+            match self.pending.keys().next() {
+                Some(&first) => self.skip_to(stream, first, emit),
+                None => break,
+            }
+        }
+    }
+
+    // The other side acknowledged `offset`, so anything missing below it was
+    // lost by the capture rather than the network and will never show up.
+    fn acknowledge<F: FnMut(StreamEvent)>(&mut self, stream: StreamKey, offset: u64, emit: &mut F) {
+        let limit = self.fin_offset.map_or(offset, |fin_offset| offset.min(fin_offset));
+        while self.next_offset < limit {
+            let target = match self.pending.keys().next() {
+                Some(&first) if first < limit => first,
+                _ => limit,
+            };
+            self.skip_to(stream, target, emit);
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.fin_offset.is_some_and(|fin_offset| self.next_offset >= fin_offset)
+    }
+
+    fn close<F: FnMut(StreamEvent)>(&mut self, stream: StreamKey, reason: CloseReason, emit: &mut F) {
+        if self.closed {
+            return;
+        }
+        if reason != CloseReason::Reset {
+            // Deliver whatever is still buffered, with gaps for the holes before it.
+            while let Some(&first) = self.pending.keys().next() {
+                self.skip_to(stream, first, emit);
+            }
+        }
+        self.pending.clear();
+        self.pending_bytes = 0;
+        self.closed = true;
+        emit(StreamEvent::End { stream, reason });
+    }
+}
+
+#[derive(Debug)]
+pub struct StreamReassembler {
+    config: StreamConfig,
+    streams: HashMap<StreamKey, HalfStream>,
+}
+
+impl StreamReassembler {
+    pub fn new(config: StreamConfig) -> Self {
+        StreamReassembler { config, streams: HashMap::new() }
+    }
+
+    pub fn active(&self) -> usize {
+        self.streams.values().filter(|stream| !stream.closed).count()
+    }
+
+    pub fn expire_with<F: FnMut(StreamEvent)>(&mut self, now: Duration, emit: &mut F) {
+        let timeout = self.config.idle_timeout;
+        let mut expired: Vec<(StreamKey, Duration)> = self
+            .streams
+            .iter()
+            .filter(|(_, stream)| now.saturating_sub(stream.last_seen) > timeout)
+            .map(|(key, stream)| (*key, stream.last_seen))
+            .collect();
+        expired.sort_by_key(|(_, last_seen)| *last_seen);
+        for (key, _) in expired {
+            if let Some(mut stream) = self.streams.remove(&key) {
+                stream.close(key, CloseReason::Timeout, emit);
+            }
+        }
+    }
+
+    pub fn flush_with<F: FnMut(StreamEvent)>(&mut self, emit: &mut F) {
+        let mut streams: Vec<(StreamKey, HalfStream)> = self.streams.drain().collect();
+        streams.sort_by_key(|(_, stream)| stream.last_seen);
+        for (key, mut stream) in streams {
+            stream.close(key, CloseReason::EndOfCapture, emit);
+        }
+    }
+
+    // Feeds one packet to the reassembler and hands every resulting event to
+    // `emit`, in stream order.
+    pub fn process_with<F: FnMut(StreamEvent)>(&mut self, now: Duration, packet: &Packet, emit: &mut F) {
+        self.expire_with(now, emit);
+        let (segment, (source_addr, dest_addr)) = match (packet.tcp(), packet.ip_addresses()) {
+            (Some(segment), Some(addresses)) => (segment, addresses),
+            _ => return,
+        };
+        let key = StreamKey {
+            source: Endpoint { addr: source_addr, port: segment.source_port },
+            dest: Endpoint { addr: dest_addr, port: segment.dest_port },
+        };
+        let flags = segment.flags;
+        let max_buffered_bytes = self.config.max_buffered_bytes;
+
+        if flags.contains(TcpFlags::RST) {
+            for key in [key, key.reverse()] {
+                if let Some(stream) = self.streams.get_mut(&key) {
+                    stream.last_seen = now;
+                    stream.close(key, CloseReason::Reset, emit);
+                }
+            }
+            return;
+        }
+        if flags.contains(TcpFlags::ACK) {
+            if let Some(reverse) = self.streams.get_mut(&key.reverse()).filter(|stream| !stream.closed) {
+                if let Some(offset) = reverse.offset(segment.ack_no) {
+                    reverse.acknowledge(key.reverse(), offset, emit);
+                    if reverse.is_finished() {
+                        reverse.close(key.reverse(), CloseReason::Fin, emit);
+                    }
+                }
+            }
+        }
+
+        let syn = flags.contains(TcpFlags::SYN);
+        // The SYN takes up the sequence number before the first data byte.
+        let data_seq = segment.sequence_no.wrapping_add(syn as u32);
+        let stream = match self.streams.get_mut(&key) {
+            // A new SYN on a closed stream starts a new connection on the same ports.
+            Some(stream) if stream.closed && syn => {
+                *stream = HalfStream::new(data_seq, now);
+                stream
+            }
+            Some(stream) => stream,
+            None => self.streams.entry(key).or_insert(HalfStream::new(data_seq, now)),
+        };
+        stream.last_seen = now;
+        if stream.closed {
+            return;
+        }
+        let offset = match stream.offset(data_seq) {
+            Some(offset) => offset,
+            None => return,
+        };
+        if !packet.payload.is_empty() {
+            stream.add(key, offset, packet.payload, max_buffered_bytes, emit);
+        }
+        if flags.contains(TcpFlags::FIN) {
+            stream.fin_offset = Some(offset + packet.payload.len() as u64);
+        }
+        if stream.is_finished() {
+            stream.close(key, CloseReason::Fin, emit);
+        }
+    }
+
+    pub fn process(&mut self, now: Duration, packet: &Packet) -> Vec<StreamEvent> {
+        let mut events: Vec<StreamEvent> = vec![];
+        self.process_with(now, packet, &mut |event| events.push(event));
+        events
+    }
+
+    pub fn flush(&mut self) -> Vec<StreamEvent> {
+        let mut events: Vec<StreamEvent> = vec![];
+        self.flush_with(&mut |event| events.push(event));
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+    use crate::packet::{dissect, LinkType};
+    use crate::testing::{tcp, CLIENT, SERVER};
+
+    fn endpoint((addr, port): ([u8; 4], u16)) -> Endpoint {
+        Endpoint { addr: IpAddr::V4(Ipv4Addr::from(addr)), port }
+    }
+
+    fn upstream() -> StreamKey {
+        StreamKey { source: endpoint(CLIENT), dest: endpoint(SERVER) }
+    }
+
+    fn data(offset: u64, data: &[u8]) -> StreamEvent {
+        StreamEvent::Data { stream: upstream(), offset, data: data.to_vec() }
+    }
+
+    // Sends a client segment and returns the events it produced.
+    fn send(streams: &mut StreamReassembler, sequence_no: u32, flags: TcpFlags, payload: &[u8]) -> Vec<StreamEvent> {
+        let bytes = tcp(true, sequence_no, 0, flags, payload);
+        streams.process(Duration::ZERO, &dissect(LinkType::IPv4, &bytes))
+    }
+
+    // Opens a client stream whose first data byte has sequence number `isn + 1`.
+    fn open(isn: u32) -> StreamReassembler {
+        let mut streams = StreamReassembler::new(StreamConfig::default());
+        assert!(send(&mut streams, isn, TcpFlags::SYN, &[]).is_empty());
+        streams
+    }
+
+    #[test]
+    fn delivers_in_order_data() {
+        let mut streams = open(999);
+        assert_eq!(send(&mut streams, 1000, TcpFlags::ACK, b"hello"), [data(0, b"hello")]);
+        assert_eq!(send(&mut streams, 1005, TcpFlags::ACK, b" world"), [data(5, b" world")]);
+    }
+
+    #[test]
+    fn reorders_out_of_order_segments() {
+        let mut streams = open(999);
+        assert!(send(&mut streams, 1005, TcpFlags::ACK, b" world").is_empty());
+        assert!(send(&mut streams, 1011, TcpFlags::ACK, b"!").is_empty());
+        assert_eq!(
+            send(&mut streams, 1000, TcpFlags::ACK, b"hello"),
+            [data(0, b"hello"), data(5, b" world"), data(11, b"!")]
+        );
+    }
+
+    #[test]
+    fn ignores_retransmissions() {
+        let mut streams = open(999);
+        send(&mut streams, 1000, TcpFlags::ACK, b"hello");
+        assert!(send(&mut streams, 1000, TcpFlags::ACK, b"hello").is_empty());
+        assert!(send(&mut streams, 1002, TcpFlags::ACK, b"llo").is_empty());
+        // A retransmitted out-of-order segment is only buffered once.
+        send(&mut streams, 1010, TcpFlags::ACK, b"!");
+        send(&mut streams, 1010, TcpFlags::ACK, b"!");
+        assert_eq!(send(&mut streams, 1005, TcpFlags::ACK, b"there"), [data(5, b"there"), data(10, b"!")]);
+    }
+
+    #[test]
+    fn trims_overlapping_segments() {
+        let mut streams = open(999);
+        send(&mut streams, 1000, TcpFlags::ACK, b"hello");
+        assert_eq!(send(&mut streams, 1003, TcpFlags::ACK, b"lo world"), [data(5, b" world")]);
+
+        // Buffered segments that overlap each other are trimmed on delivery.
+        send(&mut streams, 1016, TcpFlags::ACK, b"cdef");
+        send(&mut streams, 1014, TcpFlags::ACK, b"abcd");
+        assert_eq!(
+            send(&mut streams, 1011, TcpFlags::ACK, b"xyz"),
+            [data(11, b"xyz"), data(14, b"abcd"), data(18, b"ef")]
+        );
+    }
+
+    #[test]
+    fn offsets_continue_across_sequence_wraparound() {
+        let mut streams = open(u32::MAX - 2);
+        assert_eq!(send(&mut streams, u32::MAX - 1, TcpFlags::ACK, b"abcd"), [data(0, b"abcd")]);
+        assert_eq!(send(&mut streams, 2, TcpFlags::ACK, b"ef"), [data(4, b"ef")]);
+        // Data from before the start of the stream is dropped.
+        assert!(send(&mut streams, u32::MAX - 3, TcpFlags::ACK, b"zz").is_empty());
+    }
+
+    #[test]
+    fn offsets_grow_past_four_gigabytes() {
+        let mut stream = HalfStream::new(100, Duration::ZERO);
+        stream.next_offset = (1 << 32) + 10;
+        assert_eq!(stream.offset(110), Some((1 << 32) + 10));
+        assert_eq!(stream.offset(120), Some((1 << 32) + 20));
+        assert_eq!(stream.offset(90), Some((1 << 32) - 10));
+    }
+
+    #[test]
+    fn acknowledged_holes_become_gaps() {
+        let mut streams = open(999);
+        send(&mut streams, 1000, TcpFlags::ACK, b"hello");
+        send(&mut streams, 1010, TcpFlags::ACK, b"xyz");
+        let bytes = tcp(false, 5000, 1013, TcpFlags::ACK, &[]);
+        let events = streams.process(Duration::ZERO, &dissect(LinkType::IPv4, &bytes));
+        assert_eq!(events, [StreamEvent::Gap { stream: upstream(), offset: 5, length: 5 }, data(10, b"xyz")]);
+    }
+
+    #[test]
+    fn skips_missing_data_over_the_buffer_limit() {
+        let config = StreamConfig { max_buffered_bytes: 4, ..StreamConfig::default() };
+        let mut streams = StreamReassembler::new(config);
+        send(&mut streams, 999, TcpFlags::SYN, &[]);
+        assert!(send(&mut streams, 1002, TcpFlags::ACK, b"cd").is_empty());
+        assert_eq!(
+            send(&mut streams, 1004, TcpFlags::ACK, b"efg"),
+            [StreamEvent::Gap { stream: upstream(), offset: 0, length: 2 }, data(2, b"cd"), data(4, b"efg")]
+        );
+    }
+
+    #[test]
+    fn fin_and_reset_end_the_stream() {
+        let mut streams = open(999);
+        assert_eq!(
+            send(&mut streams, 1000, TcpFlags::FIN | TcpFlags::ACK, b"bye"),
+            [data(0, b"bye"), StreamEvent::End { stream: upstream(), reason: CloseReason::Fin }]
+        );
+        assert_eq!(streams.active(), 0);
+        // Retransmissions after the end do not reopen the stream.
+        assert!(send(&mut streams, 1000, TcpFlags::FIN | TcpFlags::ACK, b"bye").is_empty());
+
+        let mut streams = open(999);
+        send(&mut streams, 1005, TcpFlags::ACK, b"lost");
+        assert_eq!(
+            send(&mut streams, 1009, TcpFlags::RST, &[]),
+            [StreamEvent::End { stream: upstream(), reason: CloseReason::Reset }]
+        );
+    }
+
+    #[test]
+    fn flush_delivers_buffered_data() {
+        let mut streams = open(999);
+        send(&mut streams, 1003, TcpFlags::ACK, b"late");
+        assert_eq!(
+            streams.flush(),
+            [
+                StreamEvent::Gap { stream: upstream(), offset: 0, length: 3 },
+                data(3, b"late"),
+                StreamEvent::End { stream: upstream(), reason: CloseReason::EndOfCapture },
+            ]
+        );
+    }
+}
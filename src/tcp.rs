@@ -1,6 +1,9 @@
-use std::array::TryFromSliceError;
-use std::error::Error;
 use std::fmt;
+use std::net::IpAddr;
+use std::ops::{BitAnd, BitOr, BitOrAssign};
+use crate::checksum::ChecksumStatus;
+use crate::bytes::{ensure_length, read_array, read_u16, read_u32, read_u64, read_u8};
+use crate::error::ParseError;
 
 #[derive(Debug)]
 pub enum TcpOptionType {
@@ -9,7 +12,12 @@ pub enum TcpOptionType {
     MaximumSegmentSize,
     WindowScale,
     SackPermitted,
+    Sack,
     Timestamp,
+    Md5Signature,
+    AuthenticationOption,
+    Multipath,
+    FastOpen,
     Other(u8),
 }
 
@@ -21,23 +29,200 @@ impl From<u8> for TcpOptionType {
             2 => TcpOptionType::MaximumSegmentSize,
             3 => TcpOptionType::WindowScale,
             4 => TcpOptionType::SackPermitted,
+            5 => TcpOptionType::Sack,
             8 => TcpOptionType::Timestamp,
+            19 => TcpOptionType::Md5Signature,
+            29 => TcpOptionType::AuthenticationOption,
+            30 => TcpOptionType::Multipath,
+            34 => TcpOptionType::FastOpen,
             other => TcpOptionType::Other(other),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SackBlock {
+    pub left_edge: u32,
+    pub right_edge: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MptcpOption {
+    Capable {
+        version: u8,
+        flags: u8,
+        sender_key: Option<u64>,
+        receiver_key: Option<u64>,
+        data_length: Option<u16>,
+    },
+    JoinSyn {
+        backup: bool,
+        address_id: u8,
+        receiver_token: u32,
+        sender_nonce: u32,
+    },
+    JoinSynAck {
+        backup: bool,
+        address_id: u8,
+        sender_hmac: u64,
+        sender_nonce: u32,
+    },
+    JoinAck {
+        sender_hmac: Vec<u8>,
+    },
+    DataSequenceSignal {
+        data_fin: bool,
+        data_ack: Option<u64>,
+        data_sequence_no: Option<u64>,
+        subflow_sequence_no: Option<u32>,
+        data_length: Option<u16>,
+        checksum: Option<u16>,
+    },
+    AddAddress {
+        echo: bool,
+        address_id: u8,
+        address: IpAddr,
+        port: Option<u16>,
+        hmac: Option<u64>,
+    },
+    RemoveAddress {
+        address_ids: Vec<u8>,
+    },
+    Priority {
+        backup: bool,
+        address_id: Option<u8>,
+    },
+    Fail {
+        data_sequence_no: u64,
+    },
+    FastClose {
+        receiver_key: u64,
+    },
+    Reset {
+        transient: bool,
+        reason: u8,
+    },
+    Other(u8, Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TcpOption {
     EndOfOptionList,
     NoOperation,
     MaximumSegmentSize(u16),
     WindowScale(u8),
     SackPermitted,
+    Sack(Vec<SackBlock>),
     Timestamp(u32, u32),
-    Other(u8),
+    Md5Signature([u8; 16]),
+    AuthenticationOption { key_id: u8, rnext_key_id: u8, mac: Vec<u8> },
+    Multipath(MptcpOption),
+    // An empty cookie is a cookie request.
+    FastOpen(Vec<u8>),
+    Other(u8, Vec<u8>),
+    // An option that failed to parse, at its offset from the start of the options.
+    // Nothing after it is decoded.
+    BadOption { offset: usize, error: ParseError },
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct TcpFlags(u16);
+
+impl TcpFlags {
+    pub const FIN: TcpFlags = TcpFlags(0x001);
+    pub const SYN: TcpFlags = TcpFlags(0x002);
+    pub const RST: TcpFlags = TcpFlags(0x004);
+    pub const PSH: TcpFlags = TcpFlags(0x008);
+    pub const ACK: TcpFlags = TcpFlags(0x010);
+    pub const URG: TcpFlags = TcpFlags(0x020);
+    pub const ECE: TcpFlags = TcpFlags(0x040);
+    pub const CWR: TcpFlags = TcpFlags(0x080);
+    pub const NS: TcpFlags = TcpFlags(0x100);
+
+    const NAMES: [(TcpFlags, &'static str); 9] = [
+        (TcpFlags::NS, "NS"),
+        (TcpFlags::CWR, "CWR"),
+        (TcpFlags::ECE, "ECE"),
+        (TcpFlags::URG, "URG"),
+        (TcpFlags::ACK, "ACK"),
+        (TcpFlags::PSH, "PSH"),
+        (TcpFlags::RST, "RST"),
+        (TcpFlags::SYN, "SYN"),
+        (TcpFlags::FIN, "FIN"),
+    ];
+
+    pub const fn empty() -> Self {
+        TcpFlags(0)
+    }
+
+    pub const fn from_bits(bits: u16) -> Self {
+        TcpFlags(bits & 0x1FF)
+    }
+
+    pub const fn bits(self) -> u16 {
+        self.0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn contains(self, other: TcpFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn intersects(self, other: TcpFlags) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    // RFC 3168 section 6.1.1: the client offers ECN with ECE and CWR on its SYN
+    // and the server accepts with ECE alone on the SYN-ACK.
+    pub fn is_ecn_setup_syn(self) -> bool {
+        self & (TcpFlags::SYN | TcpFlags::ACK | TcpFlags::ECE | TcpFlags::CWR)
+            == TcpFlags::SYN | TcpFlags::ECE | TcpFlags::CWR
+    }
+
+    pub fn is_ecn_setup_syn_ack(self) -> bool {
+        self & (TcpFlags::SYN | TcpFlags::ACK | TcpFlags::ECE | TcpFlags::CWR)
+            == TcpFlags::SYN | TcpFlags::ACK | TcpFlags::ECE
+    }
+}
+
+impl BitOr for TcpFlags {
+    type Output = TcpFlags;
+
+    fn bitor(self, other: TcpFlags) -> TcpFlags {
+        TcpFlags(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for TcpFlags {
+    fn bitor_assign(&mut self, other: TcpFlags) {
+        self.0 |= other.0;
+    }
+}
+
+impl BitAnd for TcpFlags {
+    type Output = TcpFlags;
+
+    fn bitand(self, other: TcpFlags) -> TcpFlags {
+        TcpFlags(self.0 & other.0)
+    }
+}
+
+impl fmt::Debug for TcpFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "(empty)");
+        }
+        let names: Vec<&str> = TcpFlags::NAMES
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| *name)
+            .collect();
+        write!(f, "{}", names.join(" | "))
+    }
+}
 
 #[derive(Debug)]
 pub struct TcpSegment {
@@ -47,50 +232,46 @@ pub struct TcpSegment {
     pub ack_no: u32,
     pub header_length: u8,
     pub reserved: u8,
-    pub flag_urg: bool,
-    pub flag_ack: bool,
-    pub flag_psh: bool,
-    pub flag_rst: bool,
-    pub flag_syn: bool,
-    pub flag_fin: bool,
+    pub flags: TcpFlags,
     pub window: u16,
     pub checksum: u16,
+    pub checksum_status: ChecksumStatus,
     pub urgent_pointer: u16,
     pub options: Option<Vec<TcpOption>>,
 }
 
-struct TcpParsingError {
-    message: String,
+impl TcpSegment {
+    // Sequence numbers taken up by the segment when it carries `payload_length`
+    // bytes. SYN and FIN each take up one sequence number.
+    pub fn sequence_length(&self, payload_length: usize) -> u32 {
+        payload_length as u32 + self.flags.contains(TcpFlags::SYN) as u32 + self.flags.contains(TcpFlags::FIN) as u32
+    }
 }
 
-impl Error for TcpParsingError {}
-
-impl fmt::Display for TcpParsingError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.message)
-    }
+// Sequence number comparisons modulo 2^32 (RFC 1982 serial number arithmetic).
+pub fn seq_before(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
 }
 
-impl fmt::Debug for TcpParsingError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!( f, "TcpParsingError {{ message: {} }}", self.message)
-    }
+pub fn seq_after(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
 }
 
-pub fn parse_tcp_header(input: &[u8]) -> Result<(&[u8], TcpSegment), TryFromSliceError> {
-    let source_port = u16::from_be_bytes(<[u8; 2]>::try_from(&input[0..2])?);
-    let dest_port = u16::from_be_bytes(<[u8; 2]>::try_from(&input[2..4])?);
-    let sequence_no = u32::from_be_bytes(<[u8; 4]>::try_from(&input[4..8])?);
-    let ack_no = u32::from_be_bytes(<[u8; 4]>::try_from(&input[8..12])?);
+pub fn parse_tcp_header(input: &[u8]) -> Result<(&[u8], TcpSegment), ParseError> {
+    ensure_length(input, 20)?;
+    let source_port = read_u16(input, 0)?;
+    let dest_port = read_u16(input, 2)?;
+    let sequence_no = read_u32(input, 4)?;
+    let ack_no = read_u32(input, 8)?;
 
-    let hlen_res_flags = u16::from_be_bytes(<[u8; 2]>::try_from(&input[12..14])?);
+    let hlen_res_flags = read_u16(input, 12)?;
     let header_length = (hlen_res_flags >> 12) as u8;
-    let reserved = ((hlen_res_flags >> 6) & 0b0000_0000_0011_1111) as u8; 
-    let flags = (hlen_res_flags & 0b0000_0000_0011_1111) as u8; 
+    let reserved = ((hlen_res_flags >> 9) & 0b111) as u8;
+    let flags = TcpFlags::from_bits(hlen_res_flags);
 
-    let window = u16::from_be_bytes(<[u8; 2]>::try_from(&input[14..16])?);
-    let checksum = u16::from_be_bytes(<[u8; 2]>::try_from(&input[16..18])?);
-    let urgent_pointer = u16::from_be_bytes(<[u8; 2]>::try_from(&input[18..20])?);
+    let window = read_u16(input, 14)?;
+    let checksum = read_u16(input, 16)?;
+    let urgent_pointer = read_u16(input, 18)?;
 
     let (_, input) = input.split_at(20);
 
@@ -101,14 +282,10 @@ pub fn parse_tcp_header(input: &[u8]) -> Result<(&[u8], TcpSegment), TryFromSlic
             ack_no,
             header_length,
             reserved,
-            flag_urg: flags & 0b10_0000 == 0b10_0000,
-            flag_ack: flags & 0b01_0000 == 0b01_0000,
-            flag_psh: flags & 0b00_1000 == 0b00_1000,
-            flag_rst: flags & 0b00_0100 == 0b00_0100,
-            flag_syn: flags & 0b00_0010 == 0b00_0010,
-            flag_fin: flags & 0b00_0001 == 0b00_0001,
+            flags,
             window,
             checksum,
+            checksum_status: ChecksumStatus::Unverified,
             urgent_pointer,
             options: None,
         };
@@ -119,90 +296,234 @@ pub fn parse_tcp_header(input: &[u8]) -> Result<(&[u8], TcpSegment), TryFromSlic
     ))
 }
 
-fn parse_tcp_option(input: &[u8]) -> Result<(&[u8], TcpOption), Box<dyn Error>> {
-    if input.len() == 0 {
-        return Err(Box::new(TcpParsingError { message: "End of option list not found for tcp segment".to_string() } ))
+fn bad_option(kind: u8, reason: &'static str) -> ParseError {
+    ParseError::BadOption { kind, reason }
+}
+
+fn expect_length(kind: u8, body: &[u8], length: usize) -> Result<(), ParseError> {
+    if body.len() + 2 != length {
+        return Err(bad_option(kind, "unexpected option length"));
     }
-    let option_type = TcpOptionType::from(<u8>::try_from(input[0])?);
-    let (_, input) = input.split_at(1);
+    Ok(())
+}
+
+fn parse_mptcp_option(kind: u8, body: &[u8]) -> Result<MptcpOption, ParseError> {
+    let subtype_bits = read_u8(body, 0).map_err(|_| bad_option(kind, "missing MPTCP subtype"))?;
+    let subtype = subtype_bits >> 4;
+    let bits = subtype_bits & 0b1111;
+    // Lengths below include the kind and length bytes, as in RFC 8684.
+    let length = body.len() + 2;
+    let option = match subtype {
+        0 => {
+            if !matches!(length, 4 | 12 | 20 | 22 | 24) {
+                return Err(bad_option(kind, "unexpected MP_CAPABLE length"));
+            }
+            MptcpOption::Capable {
+                version: bits,
+                flags: read_u8(body, 1)?,
+                sender_key: if length >= 12 { Some(read_u64(body, 2)?) } else { None },
+                receiver_key: if length >= 20 { Some(read_u64(body, 10)?) } else { None },
+                data_length: if length >= 22 { Some(read_u16(body, 18)?) } else { None },
+            }
+        }
+        1 => match length {
+            12 => MptcpOption::JoinSyn {
+                backup: bits & 0b0001 != 0,
+                address_id: read_u8(body, 1)?,
+                receiver_token: read_u32(body, 2)?,
+                sender_nonce: read_u32(body, 6)?,
+            },
+            16 => MptcpOption::JoinSynAck {
+                backup: bits & 0b0001 != 0,
+                address_id: read_u8(body, 1)?,
+                sender_hmac: read_u64(body, 2)?,
+                sender_nonce: read_u32(body, 10)?,
+            },
+            24 => MptcpOption::JoinAck { sender_hmac: body[2..].to_vec() },
+            _ => return Err(bad_option(kind, "unexpected MP_JOIN length")),
+        },
+        2 => {
+            let flags = read_u8(body, 1)?;
+            let mut offset = 2;
+            let data_ack = if flags & 0b0000_0001 != 0 {
+                let value = if flags & 0b0000_0010 != 0 { read_u64(body, offset)? } else { read_u32(body, offset)? as u64 };
+                offset += if flags & 0b0000_0010 != 0 { 8 } else { 4 };
+                Some(value)
+            } else {
+                None
+            };
+            let (data_sequence_no, subflow_sequence_no, data_length, checksum) = if flags & 0b0000_0100 != 0 {
+                let wide = flags & 0b0000_1000 != 0;
+                let dsn = if wide { read_u64(body, offset)? } else { read_u32(body, offset)? as u64 };
+                offset += if wide { 8 } else { 4 };
+                let ssn = read_u32(body, offset)?;
+                let data_length = read_u16(body, offset + 4)?;
+                offset += 6;
+                let checksum = if body.len() >= offset + 2 {
+                    offset += 2;
+                    Some(read_u16(body, offset - 2)?)
+                } else {
+                    None
+                };
+                (Some(dsn), Some(ssn), Some(data_length), checksum)
+            } else {
+                (None, None, None, None)
+            };
+            if offset != body.len() {
+                return Err(bad_option(kind, "DSS length does not match its flags"));
+            }
+            MptcpOption::DataSequenceSignal {
+                data_fin: flags & 0b0001_0000 != 0,
+                data_ack,
+                data_sequence_no,
+                subflow_sequence_no,
+                data_length,
+                checksum,
+            }
+        }
+        3 => {
+            let address_id = read_u8(body, 1)?;
+            let (address, rest) = match length {
+                8 | 10 | 16 | 18 => (IpAddr::from(read_array::<4>(body, 2)?), &body[6..]),
+                20 | 22 | 28 | 30 => (IpAddr::from(read_array::<16>(body, 2)?), &body[18..]),
+                _ => return Err(bad_option(kind, "unexpected ADD_ADDR length")),
+            };
+            let (port, rest) = if rest.len() % 8 == 2 { (Some(read_u16(rest, 0)?), &rest[2..]) } else { (None, rest) };
+            MptcpOption::AddAddress {
+                echo: bits & 0b0001 != 0,
+                address_id,
+                address,
+                port,
+                hmac: if rest.len() == 8 { Some(read_u64(rest, 0)?) } else { None },
+            }
+        }
+        4 => {
+            if length < 4 {
+                return Err(bad_option(kind, "REMOVE_ADDR without an address id"));
+            }
+            MptcpOption::RemoveAddress { address_ids: body[1..].to_vec() }
+        }
+        5 => match length {
+            3 => MptcpOption::Priority { backup: bits & 0b0001 != 0, address_id: None },
+            4 => MptcpOption::Priority { backup: bits & 0b0001 != 0, address_id: Some(read_u8(body, 1)?) },
+            _ => return Err(bad_option(kind, "unexpected MP_PRIO length")),
+        },
+        6 => {
+            expect_length(kind, body, 12)?;
+            MptcpOption::Fail { data_sequence_no: read_u64(body, 2)? }
+        }
+        7 => {
+            expect_length(kind, body, 12)?;
+            MptcpOption::FastClose { receiver_key: read_u64(body, 2)? }
+        }
+        8 => {
+            expect_length(kind, body, 4)?;
+            MptcpOption::Reset { transient: bits & 0b0001 != 0, reason: read_u8(body, 1)? }
+        }
+        other => MptcpOption::Other(other, body.to_vec()),
+    };
+    Ok(option)
+}
+
+fn parse_tcp_option(input: &[u8]) -> Result<(&[u8], TcpOption), ParseError> {
+    let kind = read_u8(input, 0)?;
+    let option_type = TcpOptionType::from(kind);
     match option_type {
-        TcpOptionType::EndOfOptionList => Ok((input, TcpOption::EndOfOptionList)),
-        TcpOptionType::NoOperation => Ok((input, TcpOption::NoOperation)),
+        TcpOptionType::EndOfOptionList => return Ok((&input[1..], TcpOption::EndOfOptionList)),
+        TcpOptionType::NoOperation => return Ok((&input[1..], TcpOption::NoOperation)),
+        _ => {}
+    }
+    let length = read_u8(input, 1).map_err(|_| bad_option(kind, "missing option length"))?;
+    if length < 2 {
+        return Err(bad_option(kind, "option length shorter than its header"));
+    }
+    let body = input
+        .get(2..length as usize)
+        .ok_or(bad_option(kind, "option runs past the end of the header"))?;
+    let option = match option_type {
         TcpOptionType::MaximumSegmentSize => {
-            let _length = <u8>::try_from(input[0])?;
-            let mss =  u16::from_be_bytes(<[u8; 2]>::try_from(&input[1..3])?);
-            let (_, input) = input.split_at(3);
-            Ok((input, TcpOption::MaximumSegmentSize(mss)))
+            expect_length(kind, body, 4)?;
+            TcpOption::MaximumSegmentSize(read_u16(body, 0)?)
         },
         TcpOptionType::WindowScale => {
-            let _length = <u8>::try_from(input[0])?;
-            let shift_count = <u8>::try_from(input[1])?;
-            let (_, input) = input.split_at(2);
-            Ok((input, TcpOption::WindowScale(shift_count)))
+            expect_length(kind, body, 3)?;
+            TcpOption::WindowScale(read_u8(body, 0)?)
         },
         TcpOptionType::SackPermitted => {
-            let _length = <u8>::try_from(input[0])?;
-            let (_, input) = input.split_at(1);
-            Ok((input, TcpOption::SackPermitted))
+            expect_length(kind, body, 2)?;
+            TcpOption::SackPermitted
+        },
+        TcpOptionType::Sack => {
+            if body.is_empty() || body.len() % 8 != 0 {
+                return Err(bad_option(kind, "SACK length is not a whole number of blocks"));
+            }
+            let mut blocks: Vec<SackBlock> = vec![];
+            for block in body.chunks_exact(8) {
+                blocks.push(SackBlock { left_edge: read_u32(block, 0)?, right_edge: read_u32(block, 4)? });
+            }
+            TcpOption::Sack(blocks)
         },
         TcpOptionType::Timestamp => {
-            let _length = <u8>::try_from(input[0])?;
-            let ts_val =  u32::from_be_bytes(<[u8; 4]>::try_from(&input[1..5])?);
-            let ts_ecr =  u32::from_be_bytes(<[u8; 4]>::try_from(&input[5..9])?);
-            let (_, input) = input.split_at(9);
-            Ok((input, TcpOption::Timestamp(ts_val, ts_ecr)))
+            expect_length(kind, body, 10)?;
+            TcpOption::Timestamp(read_u32(body, 0)?, read_u32(body, 4)?)
         },
-        TcpOptionType::Other(kind) => Ok((input, TcpOption::Other(kind)))
-    }
+        TcpOptionType::Md5Signature => {
+            expect_length(kind, body, 18)?;
+            TcpOption::Md5Signature(read_array::<16>(body, 0)?)
+        },
+        TcpOptionType::AuthenticationOption => {
+            if body.len() < 2 {
+                return Err(bad_option(kind, "TCP-AO option without key ids"));
+            }
+            TcpOption::AuthenticationOption {
+                key_id: read_u8(body, 0)?,
+                rnext_key_id: read_u8(body, 1)?,
+                mac: body[2..].to_vec(),
+            }
+        },
+        TcpOptionType::Multipath => TcpOption::Multipath(parse_mptcp_option(kind, body)?),
+        TcpOptionType::FastOpen => {
+            if !(body.is_empty() || (4..=16).contains(&body.len())) || body.len() % 2 != 0 {
+                return Err(bad_option(kind, "unexpected Fast Open cookie length"));
+            }
+            TcpOption::FastOpen(body.to_vec())
+        },
+        _ => TcpOption::Other(kind, body.to_vec()),
+    };
+    Ok((&input[length as usize..], option))
 }
 
-fn parse_tcp_options(input: &[u8]) -> Result<(&[u8], Vec<TcpOption>), Box<dyn Error>> {
+fn parse_tcp_options(input: &[u8]) -> Vec<TcpOption> {
     let mut rest = input;
     let mut options: Vec<TcpOption> = vec![];
-    loop {
-        match parse_tcp_option(rest) {
-            Ok((r, option)) => {
-                rest = r;
-                match option {
-                    TcpOption::EndOfOptionList => {
-                        options.push(option);
-                        break;
-                    },
-                    TcpOption::Other(_) => {
-                        options.push(option);
-                        break;
-                    },
-                    _ => { options.push(option) }
-                }
-                if rest.len() == 0 {
-                    break;
-                }
+    while !rest.is_empty() {
+        let (r, option) = match parse_tcp_option(rest) {
+            Ok(parsed) => parsed,
+            Err(error) => {
+                options.push(TcpOption::BadOption { offset: input.len() - rest.len(), error });
+                break;
             }
-            Err(e) => return Err(e),
+        };
+        rest = r;
+        let end = option == TcpOption::EndOfOptionList;
+        options.push(option);
+        if end {
+            break;
         }
     }
-
-    Ok((rest, options))
+    options
 }
 
-pub fn parse_tcp(input: &[u8]) -> Result<(&[u8], TcpSegment), Box<dyn Error>> {
-    match parse_tcp_header(input) {
-        Ok((rest, mut segment)) => {
-            if segment.header_length > 5 {
-                let options_length = ((segment.header_length - 5) * 4) as usize;
-                if options_length <= rest.len() {
-                    if let Ok((_, options)) = parse_tcp_options(&rest[0..options_length]) {
-                        segment.options = Some(options);
-                        return Ok((&rest[options_length..], segment));
-                    }
-                    Ok((&rest[options_length..], segment))
-                } else {
-                    return Err(Box::new(TcpParsingError { message: "Bad TCP options".to_string() } ))
-                }
-            } else {
-                Ok((rest, segment))
-            }
-        }
-        Err(e) => Err(Box::new(e)),
+pub fn parse_tcp(input: &[u8]) -> Result<(&[u8], TcpSegment), ParseError> {
+    let (rest, mut segment) = parse_tcp_header(input)?;
+    if segment.header_length < 5 {
+        return Err(ParseError::BadHeaderLength { protocol: "TCP", length: segment.header_length as usize * 4 });
+    }
+    if segment.header_length > 5 {
+        let options_length = ((segment.header_length - 5) * 4) as usize;
+        ensure_length(input, segment.header_length as usize * 4)?;
+        segment.options = Some(parse_tcp_options(&rest[0..options_length]));
+        return Ok((&rest[options_length..], segment));
     }
+    Ok((rest, segment))
 }
@@ -0,0 +1,48 @@
+// Builds raw IPv4 packets for the unit tests, between a client at
+// 10.0.0.1:40000 and a server at 10.0.0.2:80.
+use crate::checksum::internet_checksum;
+use crate::tcp::TcpFlags;
+
+pub const CLIENT: ([u8; 4], u16) = ([10, 0, 0, 1], 40000);
+pub const SERVER: ([u8; 4], u16) = ([10, 0, 0, 2], 80);
+
+pub fn tcp(from_client: bool, sequence_no: u32, ack_no: u32, flags: TcpFlags, payload: &[u8]) -> Vec<u8> {
+    tcp_with(from_client, sequence_no, ack_no, flags, 65535, &[], payload)
+}
+
+// `options` must be a multiple of 4 bytes long.
+pub fn tcp_with(
+    from_client: bool,
+    sequence_no: u32,
+    ack_no: u32,
+    flags: TcpFlags,
+    window: u16,
+    options: &[u8],
+    payload: &[u8],
+) -> Vec<u8> {
+    let ((source_addr, source_port), (dest_addr, dest_port)) = match from_client {
+        true => (CLIENT, SERVER),
+        false => (SERVER, CLIENT),
+    };
+    let header_length = 20 + options.len();
+    let length = 20 + header_length + payload.len();
+
+    let mut packet: Vec<u8> = vec![0x45, 0];
+    packet.extend_from_slice(&(length as u16).to_be_bytes());
+    packet.extend_from_slice(&[0, 1, 0x40, 0, 64, 6, 0, 0]);
+    packet.extend_from_slice(&source_addr);
+    packet.extend_from_slice(&dest_addr);
+    let checksum = internet_checksum(&packet);
+    packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+    packet.extend_from_slice(&source_port.to_be_bytes());
+    packet.extend_from_slice(&dest_port.to_be_bytes());
+    packet.extend_from_slice(&sequence_no.to_be_bytes());
+    packet.extend_from_slice(&ack_no.to_be_bytes());
+    packet.extend_from_slice(&((header_length as u16 / 4) << 12 | flags.bits()).to_be_bytes());
+    packet.extend_from_slice(&window.to_be_bytes());
+    packet.extend_from_slice(&[0, 0, 0, 0]);
+    packet.extend_from_slice(options);
+    packet.extend_from_slice(payload);
+    packet
+}
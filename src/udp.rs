@@ -1,4 +1,17 @@
-use std::array::TryFromSliceError;
+use crate::checksum::ChecksumStatus;
+use crate::bytes::{ensure_length, read_u16};
+use crate::error::ParseError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UdpLengthStatus {
+    Good,
+    // The length field is smaller than the 8 byte header.
+    TooShort,
+    // The length field goes past the end of the captured data.
+    Truncated { captured: usize },
+    // The length field disagrees with the length the IP header gives the datagram.
+    IpMismatch { ip_length: usize },
+}
 
 #[derive(Debug)]
 pub struct UdpDatagram {
@@ -6,18 +19,130 @@ pub struct UdpDatagram {
     pub dest_port: u16,
     pub length: u16,
     pub checksum: u16,
+    pub checksum_status: ChecksumStatus,
+    pub length_status: UdpLengthStatus,
+    // Bytes after the end of the datagram, as given by its length field.
+    pub padding: usize,
 }
 
-pub fn parse_udp(input: &[u8]) -> Result<(&[u8], UdpDatagram), TryFromSliceError> {
-    let source_port = u16::from_be_bytes(<[u8; 2]>::try_from(&input[0..2])?);
-    let dest_port = u16::from_be_bytes(<[u8; 2]>::try_from(&input[2..4])?);
-    let length = u16::from_be_bytes(<[u8; 2]>::try_from(&input[4..6])?);
-    let checksum = u16::from_be_bytes(<[u8; 2]>::try_from(&input[6..8])?);
+// The payload ends where the length field says the datagram does, or at the
+// end of the captured data when the length is bad.
+pub fn parse_udp(input: &[u8]) -> Result<(&[u8], UdpDatagram), ParseError> {
+    ensure_length(input, 8)?;
+    let source_port = read_u16(input, 0)?;
+    let dest_port = read_u16(input, 2)?;
+    let length = read_u16(input, 4)?;
+    let checksum = read_u16(input, 6)?;
 
-    let datagram = UdpDatagram { source_port, dest_port, length, checksum };
+    let (payload, length_status, padding) = match length as usize {
+        end if end < 8 => (&input[8..], UdpLengthStatus::TooShort, 0),
+        end if end > input.len() => (&input[8..], UdpLengthStatus::Truncated { captured: input.len() }, 0),
+        end => (&input[8..end], UdpLengthStatus::Good, input.len() - end),
+    };
+
+    let datagram = UdpDatagram {
+        source_port,
+        dest_port,
+        length,
+        checksum,
+        checksum_status: ChecksumStatus::Unverified,
+        length_status,
+        padding,
+    };
 
     Ok((
-        input,
+        payload,
         datagram
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::internet_checksum;
+    use crate::packet::{dissect, LinkType};
+
+    fn udp(length: u16, data: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0x04, 0xd2, 0, 53];
+        bytes.extend_from_slice(&length.to_be_bytes());
+        bytes.extend_from_slice(&[0, 0]);
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    // `data` follows the UDP header and may run past `ip_length`, like link layer padding.
+    fn ipv4(ip_length: usize, udp_length: u16, data: &[u8]) -> Vec<u8> {
+        let mut packet: Vec<u8> = vec![0x45, 0];
+        packet.extend_from_slice(&(20 + ip_length as u16).to_be_bytes());
+        packet.extend_from_slice(&[0, 1, 0x40, 0, 64, 17, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2]);
+        let checksum = internet_checksum(&packet);
+        packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+        packet.extend_from_slice(&udp(udp_length, data));
+        packet
+    }
+
+    #[test]
+    fn payload_ends_at_the_length_field() {
+        let bytes = udp(12, b"abcdef");
+        let (payload, datagram) = parse_udp(&bytes).unwrap();
+        assert_eq!(payload, b"abcd");
+        assert_eq!(datagram.length_status, UdpLengthStatus::Good);
+        assert_eq!(datagram.padding, 2);
+    }
+
+    #[test]
+    fn bad_length_fields() {
+        let bytes = udp(4, b"abcd");
+        let (payload, datagram) = parse_udp(&bytes).unwrap();
+        assert_eq!(payload, b"abcd");
+        assert_eq!(datagram.length_status, UdpLengthStatus::TooShort);
+
+        let bytes = udp(100, b"abcd");
+        let (payload, datagram) = parse_udp(&bytes).unwrap();
+        assert_eq!(payload, b"abcd");
+        assert_eq!(datagram.length_status, UdpLengthStatus::Truncated { captured: 12 });
+        assert_eq!(datagram.padding, 0);
+
+        assert_eq!(parse_udp(&bytes[..7]).unwrap_err(), ParseError::Truncated { needed: 8, available: 7 });
+    }
+
+    #[test]
+    fn link_layer_padding_is_not_udp_padding() {
+        let bytes = ipv4(12, 12, b"abcd\0\0\0\0\0\0");
+        let packet = dissect(LinkType::IPv4, &bytes);
+        let datagram = packet.udp().unwrap();
+        assert_eq!(datagram.length_status, UdpLengthStatus::Good);
+        assert_eq!(datagram.padding, 0);
+        assert_eq!(datagram.checksum_status, ChecksumStatus::NotPresent);
+        assert_eq!(packet.payload, b"abcd");
+    }
+
+    #[test]
+    fn length_disagreeing_with_the_ip_header() {
+        let bytes = ipv4(12, 10, b"abcd\0\0");
+        let packet = dissect(LinkType::IPv4, &bytes);
+        let datagram = packet.udp().unwrap();
+        assert_eq!(datagram.length_status, UdpLengthStatus::IpMismatch { ip_length: 12 });
+        assert_eq!(datagram.padding, 2);
+        assert_eq!(packet.payload, b"ab");
+
+        // A length past the end of the IP datagram, padded out to the end of the capture.
+        let bytes = ipv4(12, 14, b"abcd\0\0");
+        let packet = dissect(LinkType::IPv4, &bytes);
+        let datagram = packet.udp().unwrap();
+        assert_eq!(datagram.length_status, UdpLengthStatus::IpMismatch { ip_length: 12 });
+        assert_eq!(packet.payload, b"abcd");
+    }
+
+    #[test]
+    fn truncated_capture_is_kept() {
+        let bytes = ipv4(108, 108, b"abcd");
+        let status = dissect(LinkType::IPv4, &bytes).udp().unwrap().length_status;
+        assert_eq!(status, UdpLengthStatus::Truncated { captured: 12 });
+
+        // Even when the lengths also disagree.
+        let bytes = ipv4(108, 200, b"abcd");
+        let status = dissect(LinkType::IPv4, &bytes).udp().unwrap().length_status;
+        assert_eq!(status, UdpLengthStatus::Truncated { captured: 12 });
+    }
+}